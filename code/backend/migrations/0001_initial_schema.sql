@@ -1,53 +1,57 @@
-CREATE
-OR REPLACE FUNCTION update_modified_column() RETURNS TRIGGER AS
+-- Schema formerly applied by hand from init.sql.
+-- IF NOT EXISTS lets databases created from init.sql adopt this migration as is.
+CREATE OR REPLACE FUNCTION update_modified_column() RETURNS TRIGGER AS
 $$
 BEGIN
-NEW.updated_at = now();
-RETURN NEW;
+    NEW.updated_at = now();
+    RETURN NEW;
 END;
 $$
 language 'plpgsql';
--- Create restaurant tables table
-DROP TABLE IF EXISTS room_users;
-DROP TABLE IF EXISTS users;
-DROP TABLE IF EXISTS rooms;
-DROP TABLE IF EXISTS services;
-CREATE TABLE services (
+
+CREATE TABLE IF NOT EXISTS services (
     id INTEGER primary key generated always as identity,
     name VARCHAR(500) NOT NULL,
     updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+DROP TRIGGER IF EXISTS update_modified_time_services ON services;
 CREATE TRIGGER update_modified_time_services BEFORE
 UPDATE
     ON services FOR EACH ROW EXECUTE PROCEDURE update_modified_column();
-CREATE TABLE users (
+
+CREATE TABLE IF NOT EXISTS users (
     id INTEGER primary key generated always as identity,
     name VARCHAR(500) NOT NULL,
     password VARCHAR(50) NOT NULL,
     updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+DROP TRIGGER IF EXISTS update_modified_time_users ON users;
 CREATE TRIGGER update_modified_time_users BEFORE
 UPDATE
     ON users FOR EACH ROW EXECUTE PROCEDURE update_modified_column();
-CREATE TABLE rooms (
+
+CREATE TABLE IF NOT EXISTS rooms (
     id INTEGER primary key generated always as identity,
     name VARCHAR(500) NOT NULL,
     updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+DROP TRIGGER IF EXISTS update_modified_time_rooms ON rooms;
 CREATE TRIGGER update_modified_time_rooms BEFORE
 UPDATE
     ON rooms FOR EACH ROW EXECUTE PROCEDURE update_modified_column();
+
 -- room_usersテーブル
-CREATE TABLE room_users (
+CREATE TABLE IF NOT EXISTS room_users (
     id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     room_id INTEGER NOT NULL REFERENCES rooms(id),
     user_id INTEGER NOT NULL REFERENCES users(id),
     updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+DROP TRIGGER IF EXISTS update_modified_time_room_users ON room_users;
 CREATE TRIGGER update_modified_time_room_users BEFORE
 UPDATE
-    ON room_users FOR EACH ROW EXECUTE PROCEDURE update_modified_column();
\ No newline at end of file
+    ON room_users FOR EACH ROW EXECUTE PROCEDURE update_modified_column();
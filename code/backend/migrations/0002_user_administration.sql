@@ -0,0 +1,7 @@
+-- bcrypt hashes are 60 characters
+ALTER TABLE users ALTER COLUMN password TYPE VARCHAR(255);
+-- Disabled users cannot log in, their rows stay for history
+ALTER TABLE users ADD COLUMN IF NOT EXISTS disabled_at TIMESTAMP;
+-- Login looks users up by name
+CREATE UNIQUE INDEX IF NOT EXISTS users_name_key ON users (name);
+CREATE UNIQUE INDEX IF NOT EXISTS room_users_room_id_user_id_key ON room_users (room_id, user_id);
@@ -0,0 +1,13 @@
+-- Role carried in the JWT, 'admin' may use /api/admin
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'user'
+    CHECK (role IN ('user', 'admin'));
+
+-- Who did what to which object
+CREATE TABLE IF NOT EXISTS audit_events (
+    id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    actor VARCHAR(500) NOT NULL,
+    action VARCHAR(100) NOT NULL,
+    target VARCHAR(500),
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,28 @@
+-- Where the audited request came from
+ALTER TABLE audit_events ADD COLUMN IF NOT EXISTS ip VARCHAR(100);
+ALTER TABLE audit_events ADD COLUMN IF NOT EXISTS request_id VARCHAR(100);
+ALTER TABLE audit_events ALTER COLUMN created_at TYPE TIMESTAMPTZ;
+ALTER TABLE audit_events ALTER COLUMN created_at SET DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX IF NOT EXISTS audit_events_actor_created_at_idx ON audit_events (actor, created_at);
+CREATE INDEX IF NOT EXISTS audit_events_action_created_at_idx ON audit_events (action, created_at);
+
+-- Append-only: rows can be inserted, never changed or removed
+CREATE OR REPLACE FUNCTION forbid_audit_change() RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$
+language 'plpgsql';
+
+DROP TRIGGER IF EXISTS audit_events_append_only ON audit_events;
+CREATE TRIGGER audit_events_append_only BEFORE
+UPDATE OR DELETE
+    ON audit_events FOR EACH ROW EXECUTE PROCEDURE forbid_audit_change();
+
+DROP TRIGGER IF EXISTS audit_events_no_truncate ON audit_events;
+CREATE TRIGGER audit_events_no_truncate BEFORE
+TRUNCATE
+    ON audit_events FOR EACH STATEMENT EXECUTE PROCEDURE forbid_audit_change();
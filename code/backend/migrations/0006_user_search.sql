@@ -0,0 +1,8 @@
+-- Fuzzy name search. pg_trgm is a trusted extension, the database owner may create it
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- % (similarity) and LIKE 'prefix%' on lower(name) / lower(display_name) use these
+CREATE INDEX IF NOT EXISTS users_name_trgm_idx ON users USING gin (lower(name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_display_name_trgm_idx ON users USING gin (lower(display_name) gin_trgm_ops);
+-- Sorting
+CREATE INDEX IF NOT EXISTS users_created_at_idx ON users (created_at);
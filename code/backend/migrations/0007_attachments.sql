@@ -0,0 +1,16 @@
+-- Files uploaded to a room. The contents live in the object store under storage_key
+CREATE TABLE IF NOT EXISTS attachments (
+    id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    -- Attachments stay in the room when the uploader is deleted
+    uploader_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(100) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    storage_key VARCHAR(255) NOT NULL UNIQUE,
+    thumbnail_key VARCHAR(255),
+    width INTEGER,
+    height INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS attachments_room_id_idx ON attachments (room_id, id);
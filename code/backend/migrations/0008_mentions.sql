@@ -0,0 +1,16 @@
+-- @name mentions of room members, the notification inbox of each user
+CREATE TABLE IF NOT EXISTS mentions (
+    id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    message_id TEXT NOT NULL,
+    -- The mention stays in the inbox when the sender is deleted
+    sender_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    excerpt VARCHAR(500) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    read_at TIMESTAMPTZ
+);
+-- A retried POST (same message_id) does not mention anyone twice
+CREATE UNIQUE INDEX IF NOT EXISTS mentions_user_id_message_id_key ON mentions (user_id, message_id);
+CREATE INDEX IF NOT EXISTS mentions_user_id_id_idx ON mentions (user_id, id DESC);
+CREATE INDEX IF NOT EXISTS mentions_unread_idx ON mentions (user_id) WHERE read_at IS NULL;
@@ -1,28 +1,87 @@
-use actix_web::{web, HttpRequest, HttpResponse, Result, Scope};
+use actix_web::{error, web, HttpRequest, HttpResponse, Scope};
 use crate::api::controller::{
+    admin_controller,
+    attachment_controller,
     auth_controller,
     user_controller,
     sse_controller,
+    health_controller,
+    metrics_controller,
+    notification_controller,
 };
+use crate::api::error::ApiError;
 use crate::api::middleware::jwt_middleware::JwtMiddleware;
+use crate::db::model::user::Role;
 
-async fn api_handler(req: HttpRequest) -> Result<HttpResponse> {
+fn json_error(err: error::JsonPayloadError) -> ApiError {
+    match err {
+        error::JsonPayloadError::Overflow { .. } | error::JsonPayloadError::OverflowKnownLength { .. } => {
+            ApiError::PayloadTooLarge(err.to_string())
+        }
+        err => ApiError::Validation(err.to_string()),
+    }
+}
+
+async fn api_handler(req: HttpRequest) -> Result<HttpResponse, ApiError> {
     let path = req.path();
-    Ok(HttpResponse::NotFound().body(format!("This API: '{}' does not exist.", path)))
+    Err(ApiError::NotFound(format!("This API: '{}' does not exist.", path)))
 }
 
-pub fn api_scope() -> Scope {
+pub fn api_scope(json_limit: usize) -> Scope {
     web::scope("/api")
+        // Malformed bodies, queries and paths get the same problem responses as handler errors
+        .app_data(web::JsonConfig::default().limit(json_limit).error_handler(|err, _| json_error(err).into()))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| ApiError::Validation(err.to_string()).into()))
+        .app_data(web::PathConfig::default().error_handler(|err, _| ApiError::Validation(err.to_string()).into()))
         .route("/auth/login", web::post().to(auth_controller::login))
         .route("/auth/current_user", web::get().to(auth_controller::current_user))
+        .route("/health/subscriber", web::get().to(health_controller::subscriber))
+        // Before the "" scope below, which would otherwise swallow /admin
+        .service(
+            web::scope("/admin")
+                .wrap(JwtMiddleware::require(Role::Admin))
+                .configure(admin_routes)
+        )
         // ↓ このスコープ（/api/user...）だけJWTミドルウェアをwrap
         .service(
             web::scope("")
-                //.wrap(JwtMiddleware)
+                //.wrap(JwtMiddleware::authenticated())
                 .route("/users", web::get().to(user_controller::get_users)) // api/users
+                // Before /users/{user_id}, which would take "me" for an id
+                .route("/users/me", web::get().to(user_controller::get_me)) // api/users/me
+                .route("/users/me", web::patch().to(user_controller::update_me)) // api/users/me
                 .route("/users/{user_id}", web::get().to(user_controller::get_user)) // api/users/{user_id}
                 .route("/sse/events", web::get().to(sse_controller::events)) // api/users
+                .route("/sse/me", web::get().to(sse_controller::personal_events)) // api/sse/me
                 .route("/sse/publish", web::post().to(sse_controller::publish)) // api/users/{user_id}
+                .route("/rooms/{room_id}/attachments", web::post().to(attachment_controller::upload))
+                .route("/attachments/{attachment_id}", web::get().to(attachment_controller::download))
+                .route("/attachments/{attachment_id}/thumbnail", web::get().to(attachment_controller::thumbnail))
+                .route("/notifications", web::get().to(notification_controller::inbox))
+                .route("/notifications/read", web::post().to(notification_controller::read_all))
+                .route("/notifications/{notification_id}", web::patch().to(notification_controller::update))
         )
         .default_service(web::route().to(api_handler))
 }
+
+// api/admin, only for tokens with the admin role
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/stats", web::get().to(admin_controller::stats))
+        .route("/sse/clients", web::get().to(sse_controller::clients))
+        .route("/audit", web::get().to(admin_controller::audit_events))
+        .route("/users/{user_id}", web::patch().to(admin_controller::rename_user))
+        .route("/users/{user_id}", web::delete().to(admin_controller::delete_user))
+        .route("/users/{user_id}/disable", web::post().to(admin_controller::disable_user))
+        .route("/users/{user_id}/enable", web::post().to(admin_controller::enable_user))
+        .route("/rooms", web::get().to(admin_controller::rooms))
+        .route("/rooms/{room_id}", web::delete().to(admin_controller::delete_room))
+        .route("/rooms/{room_id}/members", web::get().to(admin_controller::room_members))
+        .route("/rooms/{room_id}/members/{user_id}", web::delete().to(admin_controller::remove_room_member));
+}
+
+// Outside /api, where Prometheus and Kubernetes probes look by default
+pub fn root_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics_controller::metrics))
+        .route("/healthz", web::get().to(health_controller::liveness))
+        .route("/readyz", web::get().to(health_controller::readiness));
+}
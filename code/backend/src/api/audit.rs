@@ -0,0 +1,14 @@
+use actix_web::HttpRequest;
+
+use crate::api::client_ip::client_ip;
+use crate::api::middleware::request_id_middleware::request_id;
+use crate::db::model::audit_event::Actor;
+
+/// The [`Actor`] of an audit event caused by `req`, acting as `name`.
+pub fn actor(req: &HttpRequest, name: &str) -> Actor {
+    Actor {
+        name: name.to_string(),
+        ip: client_ip(req).map(|ip| ip.to_string()),
+        request_id: request_id(req),
+    }
+}
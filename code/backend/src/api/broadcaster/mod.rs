@@ -0,0 +1,481 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use actix_web::web::Bytes;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::time::{interval_at, Duration, Instant, Interval};
+
+use crate::library::metrics::METRICS;
+use crate::settings::SseSettings;
+
+/// A message as seen by SSE clients, numbered per room in the order this pod received it.
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    pub seq: u64,
+    /// SSE event name, plain messages have none.
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Returned when a user already has the maximum number of open streams.
+#[derive(Debug)]
+pub struct TooManyConnections;
+
+/// What a stream listens to: the messages of a room, or the personal events
+/// (such as mentions) of a user, whichever room they come from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Channel {
+    Room(i32),
+    User(i32),
+}
+
+/// Per-client buffer metrics.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ClientStats {
+    pub client_id: u64,
+    /// `None` for personal streams.
+    pub room_id: Option<i32>,
+    pub user: String,
+    pub delivered: u64,
+    /// Messages waiting in the broadcast buffer for this client.
+    pub buffered: usize,
+    pub lag_events: u64,
+    pub skipped: u64,
+    pub backfilled: u64,
+}
+
+/// Broadcast channel and history of one room (or personal channel).
+struct Room {
+    tx: broadcast::Sender<Envelope>,
+    seq: AtomicU64,
+    history: Mutex<VecDeque<Envelope>>,
+    history_size: usize,
+    /// When a client last joined or left, for evicting rooms nobody listens to.
+    last_client: Mutex<Instant>,
+}
+
+impl Room {
+    fn new(capacity: usize, history_size: usize) -> Self {
+        let (tx, _rx) = broadcast::channel(capacity);
+        Room {
+            tx,
+            seq: AtomicU64::new(0),
+            // Grows with the traffic of the room, most never come close to history_size
+            history: Mutex::new(VecDeque::new()),
+            history_size,
+            last_client: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn send(&self, event: Option<String>, data: String) {
+        // Numbering, history and send happen under one lock so the history
+        // order always matches the broadcast order.
+        let mut history = self.history.lock().unwrap();
+        let envelope = Envelope { seq: self.seq.fetch_add(1, Ordering::SeqCst) + 1, event, data };
+        if history.len() == self.history_size {
+            history.pop_front();
+        }
+        history.push_back(envelope.clone());
+        let _ = self.tx.send(envelope);
+    }
+
+    /// A new receiver and the seq of the last message it will not see.
+    fn subscribe(&self) -> (broadcast::Receiver<Envelope>, u64) {
+        let _history = self.history.lock().unwrap();
+        *self.last_client.lock().unwrap() = Instant::now();
+        (self.tx.subscribe(), self.seq.load(Ordering::SeqCst))
+    }
+
+    /// No client listens and none has for `ttl`.
+    fn is_idle(&self, ttl: Duration) -> bool {
+        self.tx.receiver_count() == 0 && self.last_client.lock().unwrap().elapsed() >= ttl
+    }
+
+    /// Messages with `from <= seq <= to`, or None if some were already evicted.
+    fn backfill(&self, from: u64, to: u64) -> Option<Vec<Envelope>> {
+        let history = self.history.lock().unwrap();
+        match history.front() {
+            Some(oldest) if oldest.seq <= from => Some(
+                history.iter().filter(|e| e.seq >= from && e.seq <= to).cloned().collect()
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Fans messages out to the SSE clients of each room and keeps a short history
+/// per room so clients that fall behind the broadcast buffer can be backfilled
+/// instead of losing messages.
+pub struct Broadcaster {
+    rooms: Mutex<HashMap<Channel, Arc<Room>>>,
+    config: SseSettings,
+    next_client_id: AtomicU64,
+    clients: Mutex<HashMap<u64, ClientStats>>,
+    restarting: watch::Sender<bool>,
+}
+
+impl Broadcaster {
+    pub fn new(config: SseSettings) -> Self {
+        Broadcaster {
+            rooms: Mutex::new(HashMap::new()),
+            config,
+            next_client_id: AtomicU64::new(1),
+            clients: Mutex::new(HashMap::new()),
+            restarting: watch::channel(false).0,
+        }
+    }
+
+    /// Subscribes to `channel`, creating it for its first client. Done under
+    /// the rooms lock so an eviction cannot drop the room in between.
+    fn join(&self, channel: Channel) -> (Arc<Room>, broadcast::Receiver<Envelope>, u64) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms
+            .entry(channel)
+            .or_insert_with(|| Arc::new(Room::new(self.config.capacity, self.config.history_size)))
+            .clone();
+        let (rx, last_seq) = room.subscribe();
+        (room, rx, last_seq)
+    }
+
+    /// Only clients create channels, messages for a channel nobody on this
+    /// pod listens to have no one to reach and are dropped.
+    fn send_to(&self, channel: Channel, event: Option<String>, data: String) {
+        let room = self.rooms.lock().unwrap().get(&channel).cloned();
+        if let Some(room) = room {
+            room.send(event, data);
+        }
+    }
+
+    /// Sends `data` to the clients of `room_id`, as the named SSE `event` if given.
+    pub fn send(&self, room_id: i32, event: Option<String>, data: String) {
+        self.send_to(Channel::Room(room_id), event, data);
+    }
+
+    /// Sends the named `event` to the personal streams of `user_id`.
+    pub fn send_to_user(&self, user_id: i32, event: String, data: String) {
+        self.send_to(Channel::User(user_id), Some(event), data);
+    }
+
+    /// Drops the channels (and history) nobody has listened to for
+    /// `room_idle_ttl`. Returns the number dropped.
+    pub fn evict_idle_rooms(&self) -> usize {
+        let mut rooms = self.rooms.lock().unwrap();
+        let before = rooms.len();
+        rooms.retain(|_, room| !room.is_idle(self.config.room_idle_ttl));
+        before - rooms.len()
+    }
+
+    pub fn client_stats(&self) -> Vec<ClientStats> {
+        let mut stats: Vec<ClientStats> = self.clients.lock().unwrap().values().cloned().collect();
+        stats.sort_by_key(|s| s.client_id);
+        stats
+    }
+
+    fn update_client<F: FnOnce(&mut ClientStats)>(&self, client_id: u64, f: F) {
+        if let Some(stats) = self.clients.lock().unwrap().get_mut(&client_id) {
+            f(stats);
+        }
+    }
+
+    /// Sends `server_restarting` to every open stream and closes them, so
+    /// clients reconnect (to another pod) instead of seeing a cut connection.
+    /// Returns the number of clients told.
+    pub fn restart_clients(&self) -> usize {
+        self.restarting.send_replace(true);
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Registers a new client of `user` in `room_id` and returns its event stream.
+    pub fn subscribe(
+        self: Arc<Self>,
+        room_id: i32,
+        user: &str,
+    ) -> Result<impl futures::Stream<Item = Bytes>, TooManyConnections> {
+        self.open(Channel::Room(room_id), user)
+    }
+
+    /// Registers a new personal stream of `user` (whose id is `user_id`).
+    pub fn subscribe_user(
+        self: Arc<Self>,
+        user_id: i32,
+        user: &str,
+    ) -> Result<impl futures::Stream<Item = Bytes>, TooManyConnections> {
+        self.open(Channel::User(user_id), user)
+    }
+
+    fn open(
+        self: Arc<Self>,
+        channel: Channel,
+        user: &str,
+    ) -> Result<impl futures::Stream<Item = Bytes>, TooManyConnections> {
+        let client_id = self.next_client_id.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut clients = self.clients.lock().unwrap();
+            let open = clients.values().filter(|c| c.user == user).count();
+            if self.config.max_connections_per_user > 0 && open >= self.config.max_connections_per_user {
+                return Err(TooManyConnections);
+            }
+            let room_id = match channel {
+                Channel::Room(room_id) => Some(room_id),
+                Channel::User(_) => None,
+            };
+            clients.insert(client_id, ClientStats { client_id, room_id, user: user.to_string(), ..Default::default() });
+        }
+        METRICS.sse_clients.inc();
+        let (room, rx, last_seq) = self.join(channel);
+        let heartbeat = self.config.heartbeat;
+        // Tell the client how long to wait before reconnecting.
+        let retry = Bytes::from(format!("retry: {}\n\n", self.config.retry_ms));
+
+        let restarting = self.restarting.subscribe();
+        let client = Client {
+            guard: ClientGuard { broadcaster: self, client_id },
+            room,
+            rx,
+            last_seq,
+            heartbeat: interval_at(Instant::now() + heartbeat, heartbeat),
+            restarting,
+            pending: VecDeque::from([retry]),
+            closing: false,
+        };
+        Ok(futures::stream::unfold(client, |mut client| async move {
+            client.next().await.map(|bytes| (bytes, client))
+        }))
+    }
+}
+
+/// Removes the client from the registry when its stream is dropped, which
+/// actix does once a write (at the latest the next heartbeat) to a
+/// disconnected client fails.
+struct ClientGuard {
+    broadcaster: Arc<Broadcaster>,
+    client_id: u64,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.broadcaster.clients.lock().unwrap().remove(&self.client_id);
+        METRICS.sse_clients.dec();
+    }
+}
+
+struct Client {
+    guard: ClientGuard,
+    room: Arc<Room>,
+    rx: broadcast::Receiver<Envelope>,
+    last_seq: u64,
+    heartbeat: Interval,
+    restarting: watch::Receiver<bool>,
+    pending: VecDeque<Bytes>,
+    closing: bool,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // The idle TTL of the room starts when its last client leaves
+        *self.room.last_client.lock().unwrap() = Instant::now();
+    }
+}
+
+impl Client {
+    async fn next(&mut self) -> Option<Bytes> {
+        if let Some(bytes) = self.pending.pop_front() {
+            return Some(bytes);
+        }
+        if self.closing {
+            return None;
+        }
+        // Also covers clients that connected after the shutdown began
+        if *self.restarting.borrow_and_update() {
+            return Some(self.restart_notice());
+        }
+        let received = tokio::select! {
+            received = self.rx.recv() => received,
+            _ = self.heartbeat.tick() => return Some(Bytes::from_static(b": ping\n\n")),
+            _ = self.restarting.changed() => return Some(self.restart_notice()),
+        };
+        match received {
+            Ok(envelope) => {
+                self.last_seq = envelope.seq;
+                let buffered = self.rx.len();
+                self.guard.broadcaster.update_client(self.guard.client_id, |stats| {
+                    stats.delivered += 1;
+                    stats.buffered = buffered;
+                });
+                Some(message_event(&envelope))
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                self.resync(skipped);
+                self.pending.pop_front()
+            }
+            Err(RecvError::Closed) => None,
+        }
+    }
+
+    fn restart_notice(&mut self) -> Bytes {
+        self.closing = true;
+        // Jitter, so the clients of this pod do not all reconnect in the same instant
+        let retry_ms = self.guard.broadcaster.config.retry_ms;
+        let reconnect_ms = retry_ms + rand::random_range(0..=retry_ms);
+        Bytes::from(format!(
+            "event: server_restarting\nretry: {}\ndata: {}\n\n",
+            reconnect_ms,
+            serde_json::json!({ "reason": "shutdown", "reconnect_ms": reconnect_ms })
+        ))
+    }
+
+    fn resync(&mut self, skipped: u64) {
+        let broadcaster = &self.guard.broadcaster;
+        let from = self.last_seq + 1;
+        let to = self.last_seq + skipped;
+        tracing::warn!(client_id = self.guard.client_id, from, to, "SSE client lagged");
+
+        let backfill = self.room.backfill(from, to);
+        let backfilled = backfill.as_ref().map(|b| b.len() as u64).unwrap_or(0);
+        broadcaster.update_client(self.guard.client_id, |stats| {
+            stats.lag_events += 1;
+            stats.skipped += skipped;
+            stats.backfilled += backfilled;
+        });
+        METRICS.sse_lag_events.inc();
+        METRICS.sse_skipped.inc_by(skipped);
+        METRICS.sse_backfilled.inc_by(backfilled);
+
+        self.pending.push_back(Bytes::from(format!(
+            "event: resync\ndata: {}\n\n",
+            serde_json::json!({ "from": from, "to": to, "skipped": skipped, "backfilled": backfill.is_some() })
+        )));
+        match backfill {
+            Some(envelopes) => {
+                self.pending.extend(envelopes.iter().map(message_event));
+                self.last_seq = to;
+            }
+            None => {
+                // Too far behind to recover, ask the client to reconnect.
+                self.pending.push_back(Bytes::from("event: reconnect\nretry: 1000\ndata: {\"reason\":\"lagged\"}\n\n"));
+                self.closing = true;
+            }
+        }
+    }
+}
+
+/// Frames `envelope` as one SSE event. Every line of the data goes on its own
+/// `data:` line (clients join them back with `\n`), so a line break in a
+/// message can never end the event early and start one of the sender's making.
+fn message_event(envelope: &Envelope) -> Bytes {
+    let mut event = format!("id: {}\n", envelope.seq);
+    if let Some(name) = &envelope.event {
+        event.push_str(&format!("event: {}\n", name));
+    }
+    for line in envelope.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    Bytes::from(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn settings() -> SseSettings {
+        SseSettings {
+            capacity: 16,
+            history_size: 16,
+            heartbeat: Duration::from_secs(60),
+            max_connections_per_user: 0,
+            retry_ms: 1000,
+            room_idle_ttl: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_notice_closes_open_streams() {
+        let broadcaster = Arc::new(Broadcaster::new(settings()));
+        let stream = broadcaster.clone().subscribe(1, "alice").unwrap();
+        let mut stream = Box::pin(stream);
+        assert_eq!(stream.next().await.unwrap(), "retry: 1000\n\n");
+
+        broadcaster.send(1, None, "hello".to_string());
+        let message = stream.next().await.unwrap();
+        assert!(String::from_utf8_lossy(&message).contains("data: hello"));
+
+        broadcaster.send(1, Some("profile_updated".to_string()), "{}".to_string());
+        let message = stream.next().await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&message), "id: 2\nevent: profile_updated\ndata: {}\n\n");
+
+        assert_eq!(broadcaster.restart_clients(), 1);
+        let notice = String::from_utf8(stream.next().await.unwrap().to_vec()).unwrap();
+        assert!(notice.starts_with("event: server_restarting\nretry: "), "{}", notice);
+        // Sequence numbers are per pod, useless to a client reconnecting elsewhere
+        assert!(!notice.contains("last_seq"), "{}", notice);
+        assert!(stream.next().await.is_none());
+        drop(stream);
+        assert!(broadcaster.client_stats().is_empty());
+    }
+
+    #[tokio::test]
+    async fn personal_events_reach_only_the_user() {
+        let broadcaster = Arc::new(Broadcaster::new(settings()));
+        // Nobody listens yet, nothing is kept
+        broadcaster.send_to_user(7, "mention".to_string(), "{\"id\":0}".to_string());
+
+        let mut personal = Box::pin(broadcaster.clone().subscribe_user(7, "alice").unwrap());
+        let mut room = Box::pin(broadcaster.clone().subscribe(7, "bob").unwrap());
+        assert_eq!(personal.next().await.unwrap(), "retry: 1000\n\n");
+        assert_eq!(room.next().await.unwrap(), "retry: 1000\n\n");
+
+        broadcaster.send_to_user(7, "mention".to_string(), "{\"id\":1}".to_string());
+        broadcaster.send(7, None, "hello".to_string());
+        assert_eq!(personal.next().await.unwrap(), "id: 1\nevent: mention\ndata: {\"id\":1}\n\n");
+        assert_eq!(room.next().await.unwrap(), "id: 1\ndata: hello\n\n");
+
+        let stats = broadcaster.client_stats();
+        assert_eq!(stats.iter().map(|c| c.room_id).collect::<Vec<_>>(), [None, Some(7)]);
+    }
+
+    #[test]
+    fn line_breaks_in_data_cannot_start_a_new_event() {
+        let envelope = Envelope {
+            seq: 3,
+            event: None,
+            data: "hi\n\nevent: server_restarting\r\ndata: x\rid: 99".to_string(),
+        };
+        assert_eq!(
+            message_event(&envelope),
+            "id: 3\ndata: hi\ndata: \ndata: event: server_restarting\ndata: data: x\ndata: id: 99\n\n"
+        );
+        let framed = String::from_utf8(message_event(&envelope).to_vec()).unwrap();
+        assert_eq!(framed.matches("\n\n").count(), 1);
+        assert!(framed.lines().all(|line| line.is_empty() || line.starts_with("id: ") || line.starts_with("data: ")));
+    }
+
+    #[tokio::test]
+    async fn rooms_are_created_by_clients_and_evicted_when_idle() {
+        let broadcaster = Arc::new(Broadcaster::new(SseSettings { room_idle_ttl: Duration::ZERO, ..settings() }));
+        // Publishing to rooms nobody watches keeps nothing
+        for room_id in 0..100 {
+            broadcaster.send(room_id, None, "hello".to_string());
+        }
+        assert!(broadcaster.rooms.lock().unwrap().is_empty());
+
+        let stream = broadcaster.clone().subscribe(1, "alice").unwrap();
+        broadcaster.send(1, None, "hello".to_string());
+        assert_eq!(broadcaster.evict_idle_rooms(), 0);
+        drop(stream);
+        assert_eq!(broadcaster.evict_idle_rooms(), 1);
+        assert!(broadcaster.rooms.lock().unwrap().is_empty());
+
+        // Within the TTL the room (and its history) is kept for a reconnect
+        let broadcaster = Arc::new(Broadcaster::new(settings()));
+        drop(broadcaster.clone().subscribe(1, "alice").unwrap());
+        assert_eq!(broadcaster.evict_idle_rooms(), 0);
+        assert_eq!(broadcaster.rooms.lock().unwrap().len(), 1);
+    }
+}
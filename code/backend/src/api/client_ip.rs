@@ -0,0 +1,71 @@
+use std::net::IpAddr;
+
+use actix_web::{web, HttpRequest};
+
+/// Proxies (`server.trusted_proxies`) whose `X-Forwarded-For` is believed.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+/// IP of the client that sent `req`. Anybody can send `X-Forwarded-For`, so
+/// it is only read when the connection comes from a trusted proxy, and then
+/// the last address no trusted proxy added is the client: whatever the client
+/// wrote in the header itself comes before it.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let trusted = match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted) => trusted,
+        None => return Some(peer),
+    };
+    if !trusted.0.contains(&peer) {
+        return Some(peer);
+    }
+    let forwarded = req.headers().get("X-Forwarded-For").and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let mut client = peer;
+    for hop in forwarded.rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted.0.contains(&ip) {
+                    break;
+                }
+            }
+            // Nothing before a malformed hop can be believed
+            Err(_) => break,
+        }
+    }
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request(peer: &str, forwarded: &str, trusted: &[&str]) -> HttpRequest {
+        TestRequest::default()
+            .peer_addr(format!("{}:40000", peer).parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded))
+            .app_data(web::Data::new(TrustedProxies(trusted.iter().map(|ip| ip.parse().unwrap()).collect())))
+            .to_http_request()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn forwarded_headers_from_untrusted_peers_are_ignored() {
+        assert_eq!(client_ip(&request("203.0.113.9", "198.51.100.1", &[])), ip("203.0.113.9"));
+        assert_eq!(client_ip(&request("203.0.113.9", "198.51.100.1", &["10.0.0.1"])), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn the_last_hop_before_trusted_proxies_is_the_client() {
+        let trusted = ["10.0.0.1", "10.0.0.2"];
+        // The client wrote 198.51.100.1 itself, the proxies added the rest
+        let req = request("10.0.0.1", "198.51.100.1, 203.0.113.9, 10.0.0.2", &trusted);
+        assert_eq!(client_ip(&req), ip("203.0.113.9"));
+        assert_eq!(client_ip(&request("10.0.0.1", "garbage, 10.0.0.2", &trusted)), ip("10.0.0.2"));
+        assert_eq!(client_ip(&request("10.0.0.1", "", &trusted)), ip("10.0.0.1"));
+    }
+}
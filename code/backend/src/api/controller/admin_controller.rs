@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use actix::Addr;
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+};
+use serde::Serialize;
+use validator::Validate;
+
+use crate::{
+    api::audit::actor,
+    api::broadcaster::Broadcaster,
+    api::error::ApiError,
+    api::jwt::jwt::{self, Claims},
+    api::redis::RedisActor,
+    api::requests::audit_request::AuditRequest,
+    api::requests::rename_user_request::RenameUserRequest,
+    bus::subscriber::{SubscriberHealth, SubscriberStatus},
+    db::model::user::UserData,
+    db::repository::attachment_repository::AttachmentRepository,
+    db::repository::audit_repository::{AuditFilter, AuditRepository},
+    db::repository::room_repository::RoomRepository,
+    db::repository::user_repository::UserDataRepository,
+    storage::ObjectStore,
+};
+
+// /api/admin 以下はJwtMiddleware::require(Role::Admin)済みなのでClaimsは必ずある
+
+#[derive(Serialize)]
+struct Stats {
+    users: i64,
+    disabled_users: i64,
+    rooms: i64,
+    sse_clients: usize,
+    sse_clients_by_room: BTreeMap<i32, usize>,
+    subscriber: SubscriberStatus,
+    db_pool_connections: u32,
+    db_pool_idle: usize,
+}
+
+pub async fn stats(
+    users: web::Data<UserDataRepository>,
+    rooms: web::Data<RoomRepository>,
+    broadcaster: web::Data<Broadcaster>,
+    health: web::Data<SubscriberHealth>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_count, disabled_users) = users.count().await?;
+    let clients = broadcaster.client_stats();
+    let mut sse_clients_by_room = BTreeMap::new();
+    // Personal streams are not in any room
+    for room_id in clients.iter().filter_map(|client| client.room_id) {
+        *sse_clients_by_room.entry(room_id).or_insert(0) += 1;
+    }
+    Ok(HttpResponse::Ok().json(Stats {
+        users: user_count,
+        disabled_users,
+        rooms: rooms.count().await?,
+        sse_clients: clients.len(),
+        sse_clients_by_room,
+        subscriber: health.status(),
+        db_pool_connections: pool.size(),
+        db_pool_idle: pool.num_idle(),
+    }))
+}
+
+pub async fn rename_user(
+    http_req: HttpRequest,
+    path: web::Path<i32>,
+    req: web::Json<RenameUserRequest>,
+    claims: web::ReqData<Claims>,
+    repo: web::Data<UserDataRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    req.validate()?;
+    let user_id = path.into_inner();
+    let user = find_user(&repo, user_id).await?;
+    if user.name != req.name && repo.find_by_name(&req.name).await?.is_some() {
+        return Err(name_taken(&req.name));
+    }
+    // 監査ログはリポジトリが同じトランザクションで書く
+    let renamed = match repo.rename(user_id, &req.name, &actor(&http_req, &claims.sub)).await {
+        // Taken between the check above and the update
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Err(name_taken(&req.name)),
+        result => result?.ok_or_else(|| user_not_found(user_id))?,
+    };
+    // Tokens name their user, the old ones must not keep working
+    jwt::revoke_sessions(&redis, &user.name).await?;
+    Ok(HttpResponse::Ok().json(renamed))
+}
+
+pub async fn disable_user(
+    http_req: HttpRequest,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    repo: web::Data<UserDataRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = find_user(&repo, path.into_inner()).await?;
+    not_self(&claims, &user, "disable")?;
+    repo.set_disabled(&user.name, true, &actor(&http_req, &claims.sub)).await?;
+    jwt::revoke_sessions(&redis, &user.name).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+pub async fn enable_user(
+    http_req: HttpRequest,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    repo: web::Data<UserDataRepository>,
+) -> Result<HttpResponse, ApiError> {
+    let user = find_user(&repo, path.into_inner()).await?;
+    repo.set_disabled(&user.name, false, &actor(&http_req, &claims.sub)).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+pub async fn delete_user(
+    http_req: HttpRequest,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    repo: web::Data<UserDataRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = find_user(&repo, path.into_inner()).await?;
+    not_self(&claims, &user, "delete")?;
+    // ルームの所属ごと削除、監査ログはリポジトリが書く
+    if repo.delete(user.id, &actor(&http_req, &claims.sub)).await? == 0 {
+        return Err(user_not_found(user.id));
+    }
+    jwt::revoke_sessions(&redis, &user.name).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn rooms(
+    rooms: web::Data<RoomRepository>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(rooms.list().await?))
+}
+
+pub async fn room_members(
+    path: web::Path<i32>,
+    rooms: web::Data<RoomRepository>,
+) -> Result<HttpResponse, ApiError> {
+    let room_id = path.into_inner();
+    rooms.find(room_id).await?.ok_or_else(|| room_not_found(room_id))?;
+    Ok(HttpResponse::Ok().json(rooms.members(room_id).await?))
+}
+
+pub async fn remove_room_member(
+    http_req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+    claims: web::ReqData<Claims>,
+    rooms: web::Data<RoomRepository>,
+) -> Result<HttpResponse, ApiError> {
+    let (room_id, user_id) = path.into_inner();
+    if rooms.remove_member(room_id, user_id, &actor(&http_req, &claims.sub)).await? == 0 {
+        return Err(ApiError::NotFound(format!("User {} is not a member of room {}", user_id, room_id)));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn delete_room(
+    http_req: HttpRequest,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    rooms: web::Data<RoomRepository>,
+    attachments: web::Data<AttachmentRepository>,
+    store: web::Data<dyn ObjectStore>,
+) -> Result<HttpResponse, ApiError> {
+    let room_id = path.into_inner();
+    // 添付ファイルの行はルームと一緒に消えるので、先にキーを控えておく
+    let keys = attachments.storage_keys_of_room(room_id).await?;
+    // メンバーごと削除
+    if rooms.delete(room_id, &actor(&http_req, &claims.sub)).await? == 0 {
+        return Err(room_not_found(room_id));
+    }
+    for key in &keys {
+        if let Err(e) = store.delete(key).await {
+            tracing::warn!(error = %e, key, "Failed to delete attachment of deleted room");
+        }
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn audit_events(
+    query: web::Query<AuditRequest>,
+    audit: web::Data<AuditRepository>,
+) -> Result<HttpResponse, ApiError> {
+    query.validate()?;
+    let filter = AuditFilter {
+        actor: query.actor.as_deref(),
+        action: query.action.as_deref(),
+        from: query.from,
+        to: query.to,
+        before_id: query.before_id,
+        limit: query.limit.unwrap_or(AuditRequest::DEFAULT_LIMIT),
+    };
+    Ok(HttpResponse::Ok().json(audit.query(&filter).await?))
+}
+
+async fn find_user(repo: &UserDataRepository, user_id: i32) -> Result<UserData, ApiError> {
+    repo.find(user_id).await?.ok_or_else(|| user_not_found(user_id))
+}
+
+// An admin locking themselves out is almost certainly a mistake
+fn not_self(claims: &Claims, user: &UserData, action: &str) -> Result<(), ApiError> {
+    if claims.sub == user.name {
+        return Err(ApiError::Conflict(format!("You cannot {} your own account", action)));
+    }
+    Ok(())
+}
+
+fn name_taken(name: &str) -> ApiError {
+    ApiError::Conflict(format!("The name '{}' is already taken", name))
+}
+
+fn user_not_found(user_id: i32) -> ApiError {
+    ApiError::NotFound(format!("User {} does not exist", user_id))
+}
+
+fn room_not_found(room_id: i32) -> ApiError {
+    ApiError::NotFound(format!("Room {} does not exist", room_id))
+}
@@ -0,0 +1,312 @@
+use actix::Addr;
+use actix_multipart::{Multipart, MultipartError};
+use actix_web::{
+    http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue},
+    web::{self, Bytes, BytesMut},
+    HttpRequest,
+    HttpResponse,
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::{
+    api::error::ApiError,
+    api::jwt::jwt,
+    api::redis::RedisActor,
+    db::model::attachment::{Attachment, NewAttachment},
+    db::repository::attachment_repository::AttachmentRepository,
+    db::repository::room_repository::RoomRepository,
+    db::repository::user_repository::UserDataRepository,
+    library::media,
+    settings::AttachmentSettings,
+    storage::ObjectStore,
+};
+
+// multipartのファイルのフィールド名
+const FILE_FIELD: &str = "file";
+
+#[derive(Serialize)]
+struct AttachmentResponse {
+    #[serde(flatten)]
+    attachment: Attachment,
+    url: String,
+    thumbnail_url: Option<String>,
+}
+
+impl From<Attachment> for AttachmentResponse {
+    fn from(attachment: Attachment) -> Self {
+        let url = format!("/api/attachments/{}", attachment.id);
+        let thumbnail_url = attachment.thumbnail_key.as_ref().map(|_| format!("{}/thumbnail", url));
+        AttachmentResponse { attachment, url, thumbnail_url }
+    }
+}
+
+struct Upload {
+    file_name: String,
+    declared_type: Option<String>,
+    data: Bytes,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn upload(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    payload: Multipart,
+    users: web::Data<UserDataRepository>,
+    rooms: web::Data<RoomRepository>,
+    attachments: web::Data<AttachmentRepository>,
+    store: web::Data<dyn ObjectStore>,
+    settings: web::Data<AttachmentSettings>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let room_id = path.into_inner();
+    tracing::Span::current().record("room_id", room_id);
+    let user_id = current_user_id(&req, &redis, &users).await?;
+    rooms.find(room_id).await?.ok_or_else(|| ApiError::NotFound(format!("Room {} does not exist", room_id)))?;
+    if !rooms.is_member(room_id, user_id).await? {
+        return Err(ApiError::Forbidden(format!("You are not a member of room {}", room_id)));
+    }
+
+    let upload = read_upload(payload, settings.max_bytes).await?;
+    // 申告されたContent-Typeではなく中身で判定する
+    let content_type = media::detect_type(&upload.data, upload.declared_type.as_deref());
+    if !settings.allowed_types.contains(&content_type) {
+        return Err(ApiError::UnsupportedMediaType(format!("Files of type {} are not accepted", content_type)));
+    }
+
+    let storage_key = format!("attachments/{}/{:032x}", room_id, rand::random::<u128>());
+    let thumbnail = if content_type.starts_with("image/") && settings.thumbnail_px > 0 {
+        make_thumbnail(upload.data.clone(), settings.thumbnail_px).await
+    } else {
+        None
+    };
+    let thumbnail_key = thumbnail.as_ref().map(|_| format!("{}.thumb.png", storage_key));
+
+    store.put(&storage_key, upload.data.clone(), &content_type).await?;
+    if let (Some(thumbnail), Some(key)) = (&thumbnail, &thumbnail_key) {
+        store.put(key, Bytes::from(thumbnail.png.clone()), "image/png").await?;
+    }
+    let new = NewAttachment {
+        room_id,
+        uploader_id: user_id,
+        file_name: &upload.file_name,
+        content_type: &content_type,
+        size_bytes: upload.data.len() as i64,
+        storage_key: &storage_key,
+        thumbnail_key: thumbnail_key.as_deref(),
+        width: thumbnail.as_ref().map(|t| t.width as i32),
+        height: thumbnail.as_ref().map(|t| t.height as i32),
+    };
+    let attachment = match attachments.create(&new).await {
+        Ok(attachment) => attachment,
+        Err(e) => {
+            // Nothing refers to the objects, do not leave them behind
+            for key in std::iter::once(&storage_key).chain(&thumbnail_key) {
+                if let Err(e) = store.delete(key).await {
+                    tracing::warn!(error = %e, key, "Failed to delete orphaned attachment");
+                }
+            }
+            return Err(e.into());
+        }
+    };
+    Ok(HttpResponse::Created().json(AttachmentResponse::from(attachment)))
+}
+
+pub async fn download(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    users: web::Data<UserDataRepository>,
+    rooms: web::Data<RoomRepository>,
+    attachments: web::Data<AttachmentRepository>,
+    store: web::Data<dyn ObjectStore>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let attachment = readable_attachment(&req, path.into_inner(), &users, &rooms, &attachments, &redis).await?;
+    let data = stored(&store, &attachment.storage_key).await?;
+    // Images may be shown in place, anything else is only offered for download
+    let disposition = if attachment.content_type.starts_with("image/") {
+        DispositionType::Inline
+    } else {
+        DispositionType::Attachment
+    };
+    Ok(file_response(&attachment.content_type, disposition, &attachment.file_name, data))
+}
+
+pub async fn thumbnail(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    users: web::Data<UserDataRepository>,
+    rooms: web::Data<RoomRepository>,
+    attachments: web::Data<AttachmentRepository>,
+    store: web::Data<dyn ObjectStore>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let attachment = readable_attachment(&req, path.into_inner(), &users, &rooms, &attachments, &redis).await?;
+    let key = attachment
+        .thumbnail_key
+        .as_deref()
+        .ok_or_else(|| ApiError::NotFound(format!("Attachment {} has no thumbnail", attachment.id)))?;
+    let data = stored(&store, key).await?;
+    Ok(file_response("image/png", DispositionType::Inline, &format!("{}.thumb.png", attachment.file_name), data))
+}
+
+async fn current_user_id(
+    req: &HttpRequest,
+    redis: &Addr<RedisActor>,
+    users: &UserDataRepository,
+) -> Result<i32, ApiError> {
+    let claims = jwt::verify_active(req, redis).await?;
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+    let user = users
+        .find_by_name(&claims.sub)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("The user of this token no longer exists".to_string()))?;
+    Ok(user.id)
+}
+
+// 他のルームの添付ファイルは存在も知らせない
+async fn readable_attachment(
+    req: &HttpRequest,
+    id: i64,
+    users: &UserDataRepository,
+    rooms: &RoomRepository,
+    attachments: &AttachmentRepository,
+    redis: &Addr<RedisActor>,
+) -> Result<Attachment, ApiError> {
+    let user_id = current_user_id(req, redis, users).await?;
+    let not_found = || ApiError::NotFound(format!("Attachment {} does not exist", id));
+    let attachment = attachments.find(id).await?.ok_or_else(not_found)?;
+    if !rooms.is_member(attachment.room_id, user_id).await? {
+        return Err(not_found());
+    }
+    Ok(attachment)
+}
+
+async fn stored(store: &web::Data<dyn ObjectStore>, key: &str) -> Result<Bytes, ApiError> {
+    store
+        .get(key)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("The attachment content is missing".to_string()))
+}
+
+fn multipart_error(e: MultipartError) -> ApiError {
+    ApiError::Validation(format!("Invalid multipart body: {}", e))
+}
+
+/// Reads the `file` field, other fields are skipped. Every part counts
+/// towards `max_bytes`, reading stops as soon as it is exceeded.
+async fn read_upload(mut payload: Multipart, max_bytes: usize) -> Result<Upload, ApiError> {
+    let mut upload = None;
+    let mut total = 0;
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(multipart_error)?;
+        let is_file = upload.is_none() && field.name() == Some(FILE_FIELD);
+        let file_name = field.content_disposition().and_then(|cd| cd.get_filename()).map(sanitize_file_name);
+        let declared_type = field.content_type().map(|mime| mime.to_string());
+        let mut data = BytesMut::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(multipart_error)?;
+            total += chunk.len();
+            if total > max_bytes {
+                return Err(ApiError::PayloadTooLarge(format!("Uploads are limited to {} bytes", max_bytes)));
+            }
+            if is_file {
+                data.extend_from_slice(&chunk);
+            }
+        }
+        if is_file {
+            upload = Some(Upload {
+                file_name: file_name.unwrap_or_else(|| FILE_FIELD.to_string()),
+                declared_type,
+                data: data.freeze(),
+            });
+        }
+    }
+    match upload {
+        Some(upload) if !upload.data.is_empty() => Ok(upload),
+        Some(_) => Err(ApiError::Validation("The file is empty".to_string())),
+        None => Err(ApiError::Validation(format!("Missing multipart field '{}'", FILE_FIELD))),
+    }
+}
+
+// 画像のデコードは重いのでブロッキングスレッドで。失敗してもアップロードは続ける
+async fn make_thumbnail(data: Bytes, max_px: u32) -> Option<media::Thumbnail> {
+    match web::block(move || media::thumbnail(&data, max_px)).await {
+        Ok(Ok(thumbnail)) => Some(thumbnail),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Failed to create thumbnail");
+            None
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "Thumbnail task failed");
+            None
+        }
+    }
+}
+
+/// The last path component, without control characters, at most 255 characters.
+fn sanitize_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = base.chars().filter(|c| !c.is_control()).take(255).collect();
+    let clean = clean.trim();
+    if clean.is_empty() || clean == "." || clean == ".." {
+        FILE_FIELD.to_string()
+    } else {
+        clean.to_string()
+    }
+}
+
+fn file_response(content_type: &str, disposition: DispositionType, file_name: &str, data: Bytes) -> HttpResponse {
+    let mut parameters = vec![DispositionParam::Filename(file_name.replace(|c: char| !c.is_ascii(), "_"))];
+    if !file_name.is_ascii() {
+        // RFC 6266, for clients that understand it
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: file_name.as_bytes().to_vec(),
+        }));
+    }
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition { disposition, parameters })
+        // Served as detected at upload, browsers must not guess something else
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .insert_header(("Cache-Control", "private, max-age=3600"))
+        .body(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_multipart::test::create_form_data_payload_and_headers;
+
+    fn form(field: &str, data: &'static [u8]) -> Multipart {
+        let (body, headers) = create_form_data_payload_and_headers(
+            field,
+            Some("../photo.png".to_string()),
+            Some(actix_web::mime::IMAGE_PNG),
+            Bytes::from_static(data),
+        );
+        Multipart::new(&headers, futures::stream::once(async move { Ok(body) }))
+    }
+
+    #[actix_web::test]
+    async fn reads_the_file_field_within_the_limit() {
+        let upload = read_upload(form("file", b"12345"), 5).await.unwrap();
+        assert_eq!((upload.file_name.as_str(), upload.declared_type.as_deref()), ("photo.png", Some("image/png")));
+        assert_eq!(upload.data, "12345");
+
+        assert!(matches!(read_upload(form("file", b"123456"), 5).await, Err(ApiError::PayloadTooLarge(_))));
+        assert!(matches!(read_upload(form("other", b"1"), 5).await, Err(ApiError::Validation(_))));
+    }
+
+    #[test]
+    fn file_names_lose_paths_and_control_characters() {
+        assert_eq!(sanitize_file_name("photo.png"), "photo.png");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("C:\\Users\\me\\report.pdf"), "report.pdf");
+        assert_eq!(sanitize_file_name("a\r\nb.txt"), "ab.txt");
+        assert_eq!(sanitize_file_name(".."), "file");
+        assert_eq!(sanitize_file_name("dir/"), "file");
+    }
+}
@@ -1,61 +1,71 @@
 use actix_web::{
     HttpResponse,
-    Responder,
     HttpRequest,
     web,
-    //http::StatusCode
 };
+use actix::Addr;
 use bcrypt::verify;
+use serde::Serialize;
+use serde_json::json;
+use validator::Validate;
 
 use crate::{
+    api::audit,
+    api::error::ApiError,
     api::jwt::jwt,
+    api::redis::RedisActor,
     api::requests::login_request::LoginRequest,
+    db::model::user::{Role, UserData},
+    db::repository::audit_repository::AuditRepository,
     db::repository::user_repository::UserDataRepository,
-    library::logger
 };
 
+#[derive(Serialize)]
+struct LoginResponse {
+    #[serde(flatten)]
+    user: UserData,
+    role: Role,
+    token: String,
+}
+
 // DIする場合はリポジトリもweb::Dataで渡す想定
 pub async fn login(
+    http_req: HttpRequest,
     req: web::Json<LoginRequest>,
-    repo: web::Data<UserDataRepository>
-) -> impl Responder {
-    // nameでユーザーとハッシュ取得
-    let user_result = repo.find_with_password_by_name(&req.name).await;
-
-    let (user_data, hashed_password) = match user_result {
-        Ok(Some((user_data, hashed_password))) => (user_data, hashed_password),
-        Ok(None) => return HttpResponse::Unauthorized().finish(),
-        Err(err) => {
-            logger::log(logger::Header::ERROR, &err.to_string());
-            return HttpResponse::InternalServerError().finish();
-        }
+    repo: web::Data<UserDataRepository>,
+    audit: web::Data<AuditRepository>,
+) -> Result<HttpResponse, ApiError> {
+    req.validate()?;
+    let actor = audit::actor(&http_req, &req.name);
+    // nameでユーザーとハッシュ取得 (無効なユーザーも見つからない扱い)
+    let Some((user_data, hashed_password, role)) = repo.find_with_password_by_name(&req.name).await? else {
+        audit.record_or_log(&actor, "auth.login_failed", None, json!({ "reason": "unknown_user" })).await;
+        return Err(ApiError::Unauthorized("Invalid name or password".to_string()));
     };
+    let target = format!("user:{}", user_data.id);
 
     // パスワード検証
     match verify(&req.password, &hashed_password) {
         Ok(true) => {
+            tracing::Span::current().record("user_id", user_data.id);
+            audit.record_or_log(&actor, "auth.login", Some(&target), json!({})).await;
             // JWT生成
-            match jwt::create_token(&user_data.name) {
-                Ok(_token) => {
-                    // 必要ならtokenをUserDataに追加
-                    HttpResponse::Ok().json(user_data)
-                }
-                Err(err) => {
-                    logger::log(logger::Header::ERROR, &err.to_string());
-                    return HttpResponse::InternalServerError().finish();
-                }
-            }
+            let token = jwt::create_token(&user_data.name, role).map_err(ApiError::TokenSigning)?;
+            audit.record_or_log(&actor, "auth.token_issued", Some(&target), json!({ "role": role })).await;
+            Ok(HttpResponse::Ok().json(LoginResponse { user: user_data, role, token }))
+        }
+        _ => {
+            audit.record_or_log(&actor, "auth.login_failed", Some(&target), json!({ "reason": "wrong_password" })).await;
+            Err(ApiError::Unauthorized("Invalid name or password".to_string()))
         }
-        _ => HttpResponse::Unauthorized().finish(),
     }
 }
 
-pub async fn current_user(req: HttpRequest) -> impl Responder {
-    match jwt::verify(&req) {
-        Ok(user_info) => HttpResponse::Ok().json(user_info),
-        Err(err) => {
-            logger::log(logger::Header::ERROR, &err.to_string());
-            HttpResponse::Unauthorized().finish()
-        },
-    }
+pub async fn current_user(
+    req: HttpRequest,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_info = jwt::verify_active(&req, &redis).await?;
+    tracing::Span::current().record("user_id", user_info.sub.as_str());
+    Ok(HttpResponse::Ok().json(user_info))
 }
@@ -0,0 +1,218 @@
+use std::future::Future;
+use std::time::Duration;
+
+use actix::Addr;
+use actix_web::{
+    HttpResponse,
+    web,
+};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::api::redis::{InfoCommand, RedisActor};
+use crate::bus::subscriber::SubscriberHealth;
+use crate::library::shutdown::Shutdown;
+
+// A dependency slower than this counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Check {
+    status: &'static str,
+    latency_ms: u64,
+}
+
+impl Check {
+    fn is_up(&self) -> bool {
+        self.status == "up"
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    postgres: Check,
+    redis: Check,
+    subscriber: Check,
+}
+
+// Public view of the subscriber. Errors may name hosts or carry payload
+// fragments, so they are only logged and shown in the admin stats
+#[derive(Serialize)]
+struct SubscriberSummary {
+    connected: bool,
+    consumed: u64,
+    duplicates: u64,
+    dead_lettered: u64,
+}
+
+async fn check<F: Future<Output = Result<(), String>>>(dependency: &'static str, fut: F) -> Check {
+    let started = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, fut).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("no answer within {}ms", CHECK_TIMEOUT.as_millis())),
+    };
+    if let Err(e) = &result {
+        tracing::warn!(dependency, error = %e, "Readiness check failed");
+    }
+    Check {
+        status: if result.is_ok() { "up" } else { "down" },
+        latency_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+// Liveness: the process answers, dependencies are not checked so an outage
+// does not get every pod restarted
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "up" }))
+}
+
+// Readiness: 503 while Postgres, Redis or the subscriber loop is down, so the
+// pod is taken out of the service until they are back, and for good once
+// shutdown has begun
+pub async fn readiness(
+    pool: web::Data<sqlx::PgPool>,
+    redis: web::Data<Addr<RedisActor>>,
+    health: web::Data<SubscriberHealth>,
+    shutdown: web::Data<Shutdown>,
+) -> HttpResponse {
+    let dependencies = async {
+        futures::join!(
+            check("postgres", async {
+                sqlx::query("SELECT 1").execute(pool.get_ref()).await.map(|_| ()).map_err(|e| e.to_string())
+            }),
+            check("redis", async {
+                match redis.send(InfoCommand).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(redis_error)) => Err(redis_error.to_string()),
+                    Err(mailbox_error) => Err(mailbox_error.to_string()),
+                }
+            }),
+        )
+    };
+    ready(&shutdown, &health, dependencies).await
+}
+
+// Postgres and Redis are only checked when not draining
+async fn ready(
+    shutdown: &Shutdown,
+    health: &SubscriberHealth,
+    dependencies: impl Future<Output = (Check, Check)>,
+) -> HttpResponse {
+    if shutdown.is_draining() {
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({ "status": "draining" }));
+    }
+    let (postgres, redis) = dependencies.await;
+    let subscriber = Check {
+        status: if health.is_healthy() { "up" } else { "down" },
+        latency_ms: 0,
+    };
+
+    let ready = postgres.is_up() && redis.is_up() && subscriber.is_up();
+    let body = Readiness { status: if ready { "up" } else { "down" }, postgres, redis, subscriber };
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// 503 while the subscriber loop is not consuming, so the pod can be marked unready
+pub async fn subscriber(
+    health: web::Data<SubscriberHealth>,
+) -> HttpResponse {
+    let status = health.status();
+    let summary = SubscriberSummary {
+        connected: status.connected,
+        consumed: status.consumed,
+        duplicates: status.duplicates,
+        dead_lettered: status.dead_lettered,
+    };
+    if health.is_healthy() {
+        HttpResponse::Ok().json(summary)
+    } else {
+        HttpResponse::ServiceUnavailable().json(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use serde_json::Value;
+
+    async fn up() -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn json(res: HttpResponse) -> (StatusCode, Value) {
+        let status = res.status();
+        let bytes = to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    fn healthy() -> SubscriberHealth {
+        let health = SubscriberHealth::default();
+        health.set_connected(true);
+        health
+    }
+
+    #[actix_web::test]
+    async fn ready_when_every_dependency_is_up() {
+        let dependencies = async { futures::join!(check("postgres", up()), check("redis", up())) };
+        let (status, body) = json(ready(&Shutdown::default(), &healthy(), dependencies).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "up");
+        assert_eq!(body["subscriber"]["status"], "up");
+    }
+
+    #[actix_web::test]
+    async fn unready_while_a_dependency_is_down() {
+        let dependencies = async {
+            futures::join!(
+                check("postgres", up()),
+                check("redis", async { Err("connection refused (10.0.0.7:6379)".to_string()) }),
+            )
+        };
+        let (status, body) = json(ready(&Shutdown::default(), &healthy(), dependencies).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "down");
+        assert_eq!(body["postgres"]["status"], "up");
+        assert_eq!(body["redis"]["status"], "down");
+        assert!(!body.to_string().contains("10.0.0.7"), "{}", body);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_dependency_that_does_not_answer_is_down() {
+        let check = check("postgres", std::future::pending()).await;
+        assert!(!check.is_up());
+        assert_eq!(check.latency_ms, CHECK_TIMEOUT.as_millis() as u64);
+    }
+
+    #[actix_web::test]
+    async fn unready_while_draining_without_checking_dependencies() {
+        let shutdown = Shutdown::default();
+        shutdown.begin();
+        let dependencies = async { unreachable!("dependencies are not checked while draining") };
+        let (status, body) = json(ready(&shutdown, &healthy(), dependencies).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, serde_json::json!({ "status": "draining" }));
+    }
+
+    #[actix_web::test]
+    async fn subscriber_status_does_not_expose_errors() {
+        let health = web::Data::new(SubscriberHealth::default());
+        health.set_error("redis://:secret@10.0.0.7:6379 refused".to_string());
+        let (status, body) = json(subscriber(health.clone()).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["connected"], false);
+        assert!(body.get("last_error").is_none(), "{}", body);
+        // Still there for admins
+        assert!(health.status().last_error.is_some());
+
+        health.set_connected(true);
+        let (status, _) = json(subscriber(health).await).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,17 @@
+use actix_web::{
+    HttpResponse,
+    web,
+};
+use crate::library::metrics::METRICS;
+
+// Prometheus scrape target. Pool usage is sampled here, the rest is counted as it happens
+pub async fn metrics(
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    METRICS.db_pool_connections.set(pool.size() as i64);
+    METRICS.db_pool_idle.set(pool.num_idle() as i64);
+    METRICS.db_pool_max.set(pool.options().get_max_connections() as i64);
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(METRICS.render())
+}
@@ -1,3 +1,8 @@
+pub mod admin_controller;
+pub mod attachment_controller;
 pub mod user_controller;
 pub mod auth_controller;
-pub mod sse_controller;
\ No newline at end of file
+pub mod sse_controller;
+pub mod health_controller;
+pub mod metrics_controller;
+pub mod notification_controller;
\ No newline at end of file
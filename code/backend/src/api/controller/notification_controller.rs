@@ -0,0 +1,82 @@
+use actix_web::{
+    HttpResponse,
+    HttpRequest,
+    web
+};
+use actix::Addr;
+use serde::Serialize;
+use validator::Validate;
+
+use crate::{
+    api::error::ApiError,
+    api::jwt::jwt,
+    api::redis::RedisActor,
+    api::requests::notifications_request::{NotificationsRequest, UpdateNotificationRequest},
+    db::model::mention::Mention,
+    db::model::user::UserData,
+    db::repository::mention_repository::MentionRepository,
+    db::repository::user_repository::UserDataRepository,
+};
+
+#[derive(Serialize)]
+struct Inbox {
+    items: Vec<Mention>,
+    unread_count: i64,
+}
+
+// トークンのユーザー。通知は自分の分だけ見られる
+async fn current_user(
+    req: &HttpRequest,
+    users: &UserDataRepository,
+    redis: &Addr<RedisActor>,
+) -> Result<UserData, ApiError> {
+    let claims = jwt::verify_active(req, redis).await?;
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+    users
+        .find_by_name(&claims.sub)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", claims.sub)))
+}
+
+pub async fn inbox(
+    req: HttpRequest,
+    query: web::Query<NotificationsRequest>,
+    users: web::Data<UserDataRepository>,
+    mentions: web::Data<MentionRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = current_user(&req, &users, &redis).await?;
+    query.validate()?;
+    let limit = query.limit.unwrap_or(NotificationsRequest::DEFAULT_LIMIT);
+    let items = mentions.inbox(user.id, query.unread, query.before_id, limit).await?;
+    let unread_count = mentions.unread_count(user.id).await?;
+    Ok(HttpResponse::Ok().json(Inbox { items, unread_count }))
+}
+
+pub async fn update(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Json<UpdateNotificationRequest>,
+    users: web::Data<UserDataRepository>,
+    mentions: web::Data<MentionRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = current_user(&req, &users, &redis).await?;
+    let id = id.into_inner();
+    // 他のユーザーの通知は存在しないのと同じ扱い
+    match mentions.set_read(user.id, id, body.read).await? {
+        Some(mention) => Ok(HttpResponse::Ok().json(mention)),
+        None => Err(ApiError::NotFound(format!("Notification {} not found", id))),
+    }
+}
+
+pub async fn read_all(
+    req: HttpRequest,
+    users: web::Data<UserDataRepository>,
+    mentions: web::Data<MentionRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user = current_user(&req, &users, &redis).await?;
+    let updated = mentions.mark_all_read(user.id).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "updated": updated })))
+}
@@ -1,74 +1,276 @@
 use actix_web::{
     web,
-    Error,
+    HttpRequest,
     HttpResponse,
 };
-use futures_util::StreamExt;
-use tokio::sync::broadcast;
-use tokio_stream::wrappers::BroadcastStream;
+use actix::Addr;
+use actix_web::web::Bytes;
+use futures_util::{Stream, StreamExt};
+use tracing::Instrument;
+use validator::Validate;
 use crate::{
+    api::broadcaster::{Broadcaster, TooManyConnections},
+    api::client_ip::client_ip,
+    api::error::ApiError,
+    api::jwt::jwt,
+    api::middleware::request_id_middleware::request_id,
+    api::redis::RedisActor,
+    api::requests::events_request::EventsRequest,
     api::requests::publish_request::PublishRequest,
-    library::logger,
+    bus::dedup::{DedupStore, Deduplicator},
+    bus::{new_message_id, BusMessage, MessageBus},
+    db::model::mention::NewMentions,
+    db::repository::mention_repository::MentionRepository,
+    db::repository::room_repository::RoomRepository,
+    db::repository::user_repository::UserDataRepository,
+    library::mentions::mentioned_names,
+    library::metrics::METRICS,
+    preview::LinkPreviewer,
 };
-use google_cloud_pubsub::publisher::Publisher;
-use google_cloud_googleapis::pubsub::v1::PubsubMessage;
 
-pub async fn events(
-    broadcaster: web::Data<broadcast::Sender<String>>,
-) -> Result<HttpResponse, Error> {
-    // クライアントごとに新しいReceiverを生成
-    let rx = broadcaster.subscribe();
-
-    // tokioのBroadcastStreamをfutures-utilのStreamに変換
-    let stream = BroadcastStream::new(rx)
-        .map(|msg| match msg {
-            Ok(msg) => Ok::<_, std::convert::Infallible>(web::Bytes::from(format!("data: {}\n\n", msg))),
-            Err(err) => {
-                logger::log(logger::Header::ERROR, &err.to_string());
-                Ok::<_, std::convert::Infallible>(web::Bytes::from("data: Error\n\n"))
-            },
-        });
+/// `@name`s notified per message, further ones are plain text.
+const MAX_MENTIONS: usize = 20;
+/// Characters of the message kept in a mention.
+const EXCERPT_CHARS: usize = 200;
+/// Longest `Idempotency-Key` accepted.
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 128;
 
-    Ok(HttpResponse::Ok()
+fn event_stream(stream: impl Stream<Item = Bytes> + 'static) -> HttpResponse {
+    HttpResponse::Ok()
         .content_type("text/event-stream")
         .insert_header(("Cache-Control", "no-cache"))
         .insert_header(("Content-Type", "text/event-stream"))
-        .insert_header(("Access-Control-Allow-Origin", "*"))
-        .streaming(stream))
+        .streaming(stream.map(Ok::<_, std::convert::Infallible>))
+}
+
+/// Whom a stream counts against: connections are capped per user, anonymous
+/// clients are counted per IP.
+fn stream_owner(req: &HttpRequest) -> String {
+    match jwt::verify(req) {
+        Ok(claims) => claims.sub,
+        Err(_) => client_ip(req).map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+    }
 }
 
+pub async fn events(
+    req: HttpRequest,
+    query: web::Query<EventsRequest>,
+    rooms: web::Data<RoomRepository>,
+    broadcaster: web::Data<Broadcaster>,
+) -> Result<HttpResponse, ApiError> {
+    query.validate()?;
+    let span = tracing::Span::current();
+    span.record("room_id", query.room_id);
+    // Streams create the room's channel, so only rooms that exist get one
+    rooms.find(query.room_id).await?.ok_or_else(|| room_not_found(query.room_id))?;
+    let user = stream_owner(&req);
+    span.record("user_id", user.as_str());
+    // クライアントごとに新しいReceiverを生成
+    match broadcaster.into_inner().subscribe(query.room_id, &user) {
+        Ok(stream) => Ok(event_stream(stream)),
+        Err(TooManyConnections) => Err(ApiError::TooManyRequests(format!("Too many open event streams for {}", user))),
+    }
+}
+
+/// The personal events of the signed in user (`mention`), from every room.
+pub async fn personal_events(
+    req: HttpRequest,
+    users: web::Data<UserDataRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+    broadcaster: web::Data<Broadcaster>,
+) -> Result<HttpResponse, ApiError> {
+    let claims = jwt::verify_active(&req, &redis).await?;
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+    let user = users
+        .find_by_name(&claims.sub)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", claims.sub)))?;
+    // Counted against the same per-user cap as room streams
+    match broadcaster.into_inner().subscribe_user(user.id, &claims.sub) {
+        Ok(stream) => Ok(event_stream(stream)),
+        Err(TooManyConnections) => {
+            Err(ApiError::TooManyRequests(format!("Too many open event streams for {}", claims.sub)))
+        }
+    }
+}
+
+pub async fn clients(
+    broadcaster: web::Data<Broadcaster>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(broadcaster.client_stats())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn publish(
+    http_req: HttpRequest,
     req: web::Json<PublishRequest>,
-    publisher: web::Data<Publisher>,
-    pool: web::Data<sqlx::PgPool>
-) -> HttpResponse {
-    // https://crates.io/crates/google-cloud-pubsub
-    // https://crates.io/crates/google-cloud-googleapis
-    let msg = PubsubMessage {
-        data: req.into_inner().msg.into(),
-        // Set ordering_key if needed (https://cloud.google.com/pubsub/docs/ordering)
-        ordering_key: "order".into(),
-        ..Default::default()
-     };
-    //broadcaster.send(req.into_inner().msg).unwrap();
-    // Send a message. There are also `publish_bulk` and `publish_immediately` methods.
-    let awaiter = publisher.publish(msg).await;
-    // The get method blocks until a server-generated ID or an error is returned for the published message.
-    match awaiter.get().await {
-        Ok(_) => {
-            HttpResponse::Ok()
-            .insert_header(("Cache-Control", "no-cache"))
-            .insert_header(("Content-Type", "text/event-stream"))
-            .insert_header(("Access-Control-Allow-Origin", "*")) // TODO: ここは要修正
-            .body("Message broadcasted")
-        },
-        Err(err) => {
-            logger::log(logger::Header::ERROR, &format!("Failed to publish message: {}", err));
-            HttpResponse::InternalServerError()
-                .insert_header(("Cache-Control", "no-cache"))
-                .insert_header(("Content-Type", "text/event-stream"))
-                .insert_header(("Access-Control-Allow-Origin", "*")) // TODO: ここは要修正
-                .body("Failed to publish message")
-        },
+    message_bus: web::Data<dyn MessageBus>,
+    link_previewer: web::Data<LinkPreviewer>,
+    users: web::Data<UserDataRepository>,
+    mentions: web::Data<MentionRepository>,
+    rooms: web::Data<RoomRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+    published: web::Data<Deduplicator>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    req.validate()?;
+    let idempotency_key = idempotency_key(&http_req)?;
+    tracing::Span::current().record("room_id", req.room_id);
+    rooms.find(req.room_id).await?.ok_or_else(|| room_not_found(req.room_id))?;
+    // A retried POST with the same Idempotency-Key gets the same message_id,
+    // so subscribers show it only once
+    let message_id = match idempotency_key {
+        Some(key) => format!("room-{}:{}", req.room_id, key),
+        None => new_message_id(),
+    };
+    let urls = link_previewer.urls(&req.msg);
+    // Only signed in users mention anyone, publishing itself stays open
+    let sender = if mentioned_names(&req.msg, 1).is_empty() {
+        None
+    } else {
+        jwt::verify_active(&http_req, &redis).await.ok().map(|claims| claims.sub)
+    };
+    let text = req.msg.clone();
+    let mut msg = BusMessage::for_room(req.room_id, req.msg.into(), message_id.clone());
+    // Lets the subscriber side log under the same id as this request
+    let request_id = request_id(&http_req);
+    if let Some(request_id) = &request_id {
+        msg.attributes.insert("request_id".to_string(), request_id.clone());
+    }
+    let started = std::time::Instant::now();
+    let result = message_bus.publish(msg).await;
+    METRICS.bus_publish_duration.observe(started.elapsed().as_secs_f64());
+    METRICS.bus_publish.with_label_values(&[if result.is_ok() { "ok" } else { "error" }]).inc();
+    result?;
+    // A retry is published again, in case the first attempt never reached the
+    // bus, but previews and mentions were already taken care of by the first
+    if idempotency_key.is_some() && !published.first_delivery(&message_id).await {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "message_id": message_id })));
+    }
+    // Previews follow as a message_enriched event, the response does not wait for them
+    let permit = if urls.is_empty() { None } else { link_previewer.permit(&urls) };
+    if let Some(permit) = permit {
+        let (link_previewer, message_bus) = (link_previewer.into_inner(), message_bus.clone().into_inner());
+        let room_id = req.room_id;
+        let message_id = message_id.clone();
+        actix_web::rt::spawn(
+            async move {
+                link_previewer.enrich(message_bus.as_ref(), room_id, message_id, request_id, urls).await;
+                drop(permit);
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+    if let Some(sender) = sender {
+        let notified = notify_mentions(&sender, req.room_id, &message_id, &text, &users, &mentions, message_bus.as_ref());
+        if let Err(e) = notified.await {
+            tracing::error!(error = %e, "Failed to record mentions");
+        }
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "message_id": message_id })))
+}
+
+/// The `Idempotency-Key` header, if sent. It ends up in Redis keys and bus
+/// attributes, so only short keys of URL safe characters are taken.
+fn idempotency_key(req: &HttpRequest) -> Result<Option<&str>, ApiError> {
+    let Some(value) = req.headers().get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let key = value.to_str().ok().filter(|key| {
+        (1..=MAX_IDEMPOTENCY_KEY_LEN).contains(&key.len())
+            && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    });
+    match key {
+        Some(key) => Ok(Some(key)),
+        None => Err(ApiError::Validation(format!(
+            "Idempotency-Key must be 1 to {} characters of A-Z, a-z, 0-9, '-' and '_'",
+            MAX_IDEMPOTENCY_KEY_LEN
+        ))),
+    }
+}
+
+fn room_not_found(room_id: i32) -> ApiError {
+    ApiError::NotFound(format!("Room {} does not exist", room_id))
+}
+
+// The message is already out, so a failure here costs the mentioned users
+// their notification and is not reported to the sender
+async fn notify_mentions(
+    sender_name: &str,
+    room_id: i32,
+    message_id: &str,
+    msg: &str,
+    users: &UserDataRepository,
+    mentions: &MentionRepository,
+    message_bus: &dyn MessageBus,
+) -> Result<(), ApiError> {
+    let mut names = mentioned_names(msg, MAX_MENTIONS);
+    names.push(sender_name.to_string());
+    let members = users.members_named(room_id, &names).await?;
+    // ルームのメンバーだけがメンバーをメンションできる
+    let Some(sender) = members.iter().find(|member| member.name == sender_name) else {
+        return Ok(());
+    };
+    let user_ids: Vec<i32> = members.iter().filter(|member| member.id != sender.id).map(|member| member.id).collect();
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+    let excerpt: String = msg.chars().take(EXCERPT_CHARS).collect();
+    let new = NewMentions { room_id, message_id, sender_id: sender.id, excerpt: &excerpt, user_ids: &user_ids };
+    // A retried POST creates (and announces) nothing new
+    for mention in mentions.create(&new).await? {
+        let data = serde_json::to_vec(&mention).expect("a mention always serializes");
+        let event = BusMessage::event_for_user(room_id, mention.user_id, "mention", data);
+        if let Err(e) = message_bus.publish(event).await {
+            tracing::warn!(error = %e, user_id = mention.user_id, "Failed to publish mention");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use actix_web::test::TestRequest;
+    use actix_web::ResponseError;
+    use crate::settings::SseSettings;
+
+    #[actix_web::test]
+    async fn spoofed_forwarded_headers_share_the_peers_cap() {
+        let broadcaster = Arc::new(Broadcaster::new(SseSettings {
+            capacity: 16,
+            history_size: 16,
+            heartbeat: Duration::from_secs(60),
+            max_connections_per_user: 1,
+            retry_ms: 1000,
+            room_idle_ttl: Duration::from_secs(60),
+        }));
+        let request = |forwarded: &str| {
+            TestRequest::default()
+                .peer_addr("203.0.113.9:40000".parse().unwrap())
+                .insert_header(("X-Forwarded-For", forwarded))
+                .to_http_request()
+        };
+
+        let first = stream_owner(&request("198.51.100.1"));
+        let second = stream_owner(&request("198.51.100.2"));
+        assert_eq!(first, "203.0.113.9");
+        assert_eq!(second, first);
+        let _open = broadcaster.clone().subscribe(1, &first).ok().unwrap();
+        assert!(broadcaster.clone().subscribe(1, &second).is_err());
+    }
+
+    #[test]
+    fn idempotency_keys_are_bounded_to_url_safe_characters() {
+        let key = |value: &str| TestRequest::default().insert_header(("Idempotency-Key", value)).to_http_request();
+        assert_eq!(idempotency_key(&TestRequest::default().to_http_request()).unwrap(), None);
+        assert_eq!(idempotency_key(&key("a1_B-2")).unwrap(), Some("a1_B-2"));
+        assert!(idempotency_key(&key(&"k".repeat(128))).unwrap().is_some());
+        for invalid in ["", "has space", "a:b", "ü", &"k".repeat(129)] {
+            let err = idempotency_key(&key(invalid)).unwrap_err();
+            assert_eq!(err.status_code(), 422, "{:?}", invalid);
+        }
     }
 }
@@ -1,40 +1,115 @@
 use actix_web::{
     HttpResponse,
-    Responder,
     HttpRequest,
     web
 };
+use actix::Addr;
+use serde::Serialize;
+use validator::Validate;
+
 use crate::{
-    db::repository::user_repository::UserDataRepository,
-    library::logger,
+    api::error::ApiError,
+    api::jwt::jwt,
+    api::redis::RedisActor,
+    api::requests::update_profile_request::UpdateProfileRequest,
+    api::requests::users_request::{SortOrder, UsersRequest},
+    bus::{BusMessage, MessageBus},
+    db::model::user::{Profile, UserData},
+    db::repository::room_repository::RoomRepository,
+    db::repository::user_repository::{UserDataRepository, UserSearch},
 };
 
+#[derive(Serialize)]
+struct UserPage<T> {
+    items: Vec<T>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
+
 pub async fn get_users(
-    _req: HttpRequest,
-    pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
-    let repo = UserDataRepository::new(pool.get_ref().clone()); // <- ここでリポジトリ作成
-    match repo.list().await {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => {
-            logger::log(logger::Header::ERROR, &err.to_string());
-            HttpResponse::InternalServerError().finish()
-        }
+    query: web::Query<UsersRequest>,
+    repo: web::Data<UserDataRepository>,
+) -> Result<HttpResponse, ApiError> {
+    query.validate()?;
+    let (page, per_page) = (query.page(), query.per_page());
+    let search = UserSearch {
+        query: query.q.as_deref(),
+        sort: query.sort,
+        descending: query.order == SortOrder::Desc,
+        limit: per_page,
+        offset: (page - 1).saturating_mul(per_page),
+    };
+    let (users, total) = repo.search(&search).await?;
+    if query.full {
+        return Ok(HttpResponse::Ok().json(UserPage { items: users, total, page, per_page }));
     }
+    let items: Vec<UserData> = users.into_iter().map(UserData::from).collect();
+    Ok(HttpResponse::Ok().json(UserPage { items, total, page, per_page }))
 }
 
 pub async fn get_user(
     _req: HttpRequest,
     user_id: web::Path<i32>,
     pool: web::Data<sqlx::PgPool>
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let repo = UserDataRepository::new(pool.get_ref().clone()); // <- ここでリポジトリ作成
-    match repo.find(user_id.into_inner()).await {
-        Ok(Some(user)) => HttpResponse::Ok().json(user),
-        Ok(None) => HttpResponse::NotFound().body("User not found"),
-        Err(err) => {
-            logger::log(logger::Header::ERROR, &err.to_string());
-            HttpResponse::InternalServerError().finish()
+    let user_id = user_id.into_inner();
+    match repo.profile(user_id).await? {
+        Some(user) => Ok(HttpResponse::Ok().json(user)),
+        None => Err(ApiError::NotFound(format!("User {} not found", user_id))),
+    }
+}
+
+pub async fn get_me(
+    req: HttpRequest,
+    repo: web::Data<UserDataRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+) -> Result<HttpResponse, ApiError> {
+    let claims = jwt::verify_active(&req, &redis).await?;
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+    let user = repo
+        .find_by_name(&claims.sub)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", claims.sub)))?;
+    match repo.profile(user.id).await? {
+        Some(profile) => Ok(HttpResponse::Ok().json(profile)),
+        None => Err(ApiError::NotFound(format!("User {} not found", claims.sub))),
+    }
+}
+
+pub async fn update_me(
+    req: HttpRequest,
+    body: web::Json<UpdateProfileRequest>,
+    repo: web::Data<UserDataRepository>,
+    rooms: web::Data<RoomRepository>,
+    redis: web::Data<Addr<RedisActor>>,
+    message_bus: web::Data<dyn MessageBus>,
+) -> Result<HttpResponse, ApiError> {
+    let claims = jwt::verify_active(&req, &redis).await?;
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+    body.validate()?;
+    let profile = repo
+        .update_profile(&claims.sub, &body.changes())
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", claims.sub)))?;
+    // 同じルームのメンバーにキャッシュを更新させる
+    notify_profile_updated(&profile, &rooms, &message_bus).await?;
+    Ok(HttpResponse::Ok().json(profile))
+}
+
+// The profile is already saved, so a bus failure only costs other clients
+// the live refresh and is not reported to the caller
+async fn notify_profile_updated(
+    profile: &Profile,
+    rooms: &RoomRepository,
+    message_bus: &web::Data<dyn MessageBus>,
+) -> Result<(), ApiError> {
+    let data = serde_json::to_vec(profile).expect("a profile always serializes");
+    for room_id in rooms.room_ids_of(profile.id).await? {
+        if let Err(e) = message_bus.publish(BusMessage::event_for_room(room_id, "profile_updated", data.clone())).await {
+            tracing::warn!(error = %e, room_id, "Failed to publish profile_updated");
         }
     }
+    Ok(())
 }
@@ -0,0 +1,311 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+
+use crate::bus::BusError;
+use crate::storage::StoreError;
+
+/// Error returned by every handler. Rendered as an RFC 7807 problem document
+/// with a stable `code` clients can match on.
+#[derive(Debug)]
+pub enum ApiError {
+    Database(sqlx::Error),
+    Redis(redis::RedisError),
+    Jwt(jsonwebtoken::errors::Error),
+    /// A token could not be issued, unlike `Jwt` not the client's fault.
+    TokenSigning(jsonwebtoken::errors::Error),
+    Mailbox(actix::MailboxError),
+    Bus(BusError),
+    Storage(StoreError),
+    Validation(String),
+    /// Request payload rejected by its `Validate` rules, reported per field.
+    InvalidFields(validator::ValidationErrors),
+    Unauthorized(String),
+    /// Authenticated, but not allowed to do this.
+    Forbidden(String),
+    NotFound(String),
+    /// Clashes with the current state, e.g. a name already taken.
+    Conflict(String),
+    TooManyRequests(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'a str,
+    status: u16,
+    detail: String,
+    code: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+#[derive(Serialize)]
+struct FieldError {
+    field: String,
+    code: String,
+    message: String,
+}
+
+impl ApiError {
+    /// Stable machine readable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Database(_) => "database_error",
+            ApiError::Redis(_) => "redis_error",
+            ApiError::Jwt(_) => "invalid_token",
+            ApiError::TokenSigning(_) => "token_signing_failed",
+            ApiError::Mailbox(_) => "actor_unavailable",
+            ApiError::Bus(_) => "message_bus_error",
+            ApiError::Storage(_) => "storage_error",
+            ApiError::Validation(_) | ApiError::InvalidFields(_) => "validation_failed",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            ApiError::Database(_) => "Database error",
+            ApiError::Redis(_) => "Cache error",
+            ApiError::Jwt(_) => "Invalid token",
+            ApiError::TokenSigning(_) => "Token signing failed",
+            ApiError::Mailbox(_) => "Service unavailable",
+            ApiError::Bus(_) => "Message bus error",
+            ApiError::Storage(_) => "Storage error",
+            ApiError::Validation(_) | ApiError::InvalidFields(_) => "Validation failed",
+            ApiError::Unauthorized(_) => "Unauthorized",
+            ApiError::Forbidden(_) => "Forbidden",
+            ApiError::NotFound(_) => "Not found",
+            ApiError::Conflict(_) => "Conflict",
+            ApiError::TooManyRequests(_) => "Too many requests",
+            ApiError::PayloadTooLarge(_) => "Payload too large",
+            ApiError::UnsupportedMediaType(_) => "Unsupported media type",
+        }
+    }
+
+    /// Text shown to the client. Details of server side failures are only logged.
+    fn detail(&self) -> String {
+        match self {
+            ApiError::Validation(detail)
+            | ApiError::Unauthorized(detail)
+            | ApiError::Forbidden(detail)
+            | ApiError::NotFound(detail)
+            | ApiError::Conflict(detail)
+            | ApiError::TooManyRequests(detail)
+            | ApiError::PayloadTooLarge(detail)
+            | ApiError::UnsupportedMediaType(detail) => detail.clone(),
+            ApiError::InvalidFields(_) => "One or more fields are invalid".to_string(),
+            ApiError::Jwt(_) => "The token is invalid or expired".to_string(),
+            _ => "The request could not be completed, please retry later".to_string(),
+        }
+    }
+
+    /// Field level errors, sorted by field so responses are stable.
+    fn field_errors(&self) -> Vec<FieldError> {
+        let ApiError::InvalidFields(errors) = self else {
+            return Vec::new();
+        };
+        let mut fields: Vec<FieldError> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |e| FieldError {
+                    field: field.to_string(),
+                    code: e.code.to_string(),
+                    message: e.message.as_ref().map(|m| m.to_string()).unwrap_or_else(|| e.code.to_string()),
+                })
+            })
+            .collect();
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+        fields
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Database(e) => write!(f, "database error: {}", e),
+            ApiError::Redis(e) => write!(f, "redis error: {}", e),
+            ApiError::Jwt(e) => write!(f, "jwt error: {}", e),
+            ApiError::TokenSigning(e) => write!(f, "token signing failed: {}", e),
+            ApiError::Mailbox(e) => write!(f, "mailbox error: {}", e),
+            ApiError::Bus(e) => write!(f, "{}", e),
+            ApiError::Storage(e) => write!(f, "{}", e),
+            ApiError::Validation(e) => write!(f, "validation failed: {}", e),
+            ApiError::InvalidFields(e) => write!(f, "validation failed: {}", e),
+            ApiError::Unauthorized(e) => write!(f, "unauthorized: {}", e),
+            ApiError::Forbidden(e) => write!(f, "forbidden: {}", e),
+            ApiError::NotFound(e) => write!(f, "not found: {}", e),
+            ApiError::Conflict(e) => write!(f, "conflict: {}", e),
+            ApiError::TooManyRequests(e) => write!(f, "too many requests: {}", e),
+            ApiError::PayloadTooLarge(e) => write!(f, "payload too large: {}", e),
+            ApiError::UnsupportedMediaType(e) => write!(f, "unsupported media type: {}", e),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Database(_) | ApiError::TokenSigning(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Redis(_) | ApiError::Mailbox(_) | ApiError::Bus(_) | ApiError::Storage(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::Jwt(_) | ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Validation(_) | ApiError::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            tracing::error!(code = self.code(), error = %self, "Request failed");
+        }
+        let problem = Problem {
+            problem_type: format!("/errors/{}", self.code()),
+            title: self.title(),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code(),
+            errors: self.field_errors(),
+        };
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .json(problem)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl From<redis::RedisError> for ApiError {
+    fn from(e: redis::RedisError) -> Self {
+        ApiError::Redis(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        ApiError::Jwt(e)
+    }
+}
+
+impl From<actix::MailboxError> for ApiError {
+    fn from(e: actix::MailboxError) -> Self {
+        ApiError::Mailbox(e)
+    }
+}
+
+impl From<BusError> for ApiError {
+    fn from(e: BusError) -> Self {
+        ApiError::Bus(e)
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        ApiError::Storage(e)
+    }
+}
+
+impl From<validator::ValidationErrors> for ApiError {
+    fn from(e: validator::ValidationErrors) -> Self {
+        ApiError::InvalidFields(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use serde_json::Value;
+    use validator::Validate;
+
+    async fn problem(err: ApiError) -> (StatusCode, String, Value) {
+        let res = err.error_response();
+        let status = res.status();
+        let content_type = res.headers().get("Content-Type").unwrap().to_str().unwrap().to_string();
+        let body: Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["status"], status.as_u16());
+        (status, content_type, body)
+    }
+
+    #[derive(Validate)]
+    struct Form {
+        #[validate(length(min = 1))]
+        zeta: String,
+        #[validate(range(min = 1))]
+        alpha: i32,
+        #[validate(length(min = 1))]
+        mid: String,
+    }
+
+    #[test]
+    fn codes_statuses_and_titles() {
+        let cases = [
+            (ApiError::Database(sqlx::Error::RowNotFound), "database_error", 500, "Database error"),
+            (ApiError::Validation("bad".to_string()), "validation_failed", 422, "Validation failed"),
+            (ApiError::Unauthorized("no".to_string()), "unauthorized", 401, "Unauthorized"),
+            (ApiError::Forbidden("no".to_string()), "forbidden", 403, "Forbidden"),
+            (ApiError::NotFound("gone".to_string()), "not_found", 404, "Not found"),
+            (ApiError::Conflict("taken".to_string()), "conflict", 409, "Conflict"),
+            (ApiError::TooManyRequests("slow down".to_string()), "too_many_requests", 429, "Too many requests"),
+            (ApiError::Bus(BusError::Publish("down".to_string())), "message_bus_error", 503, "Message bus error"),
+        ];
+        for (err, code, status, title) in cases {
+            assert_eq!((err.code(), err.status_code().as_u16(), err.title()), (code, status, title), "{}", err);
+        }
+    }
+
+    #[test]
+    fn token_errors_are_the_clients_only_when_verifying() {
+        let err = jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat);
+        assert_eq!(ApiError::from(err.clone()).status_code(), StatusCode::UNAUTHORIZED);
+        let signing = ApiError::TokenSigning(err);
+        assert_eq!(signing.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(signing.code(), "token_signing_failed");
+    }
+
+    #[actix_web::test]
+    async fn server_errors_have_a_generic_detail() {
+        let err = ApiError::Database(sqlx::Error::Protocol("password authentication failed for myuser".to_string()));
+        let (status, content_type, body) = problem(err).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!(body["type"], "/errors/database_error");
+        assert_eq!(body["code"], "database_error");
+        assert_eq!(body["detail"], "The request could not be completed, please retry later");
+        assert!(!body.to_string().contains("myuser"), "{}", body);
+        assert!(body.get("errors").is_none());
+    }
+
+    #[actix_web::test]
+    async fn field_errors_are_sorted_by_field() {
+        let form = Form { zeta: String::new(), alpha: 0, mid: String::new() };
+        let (status, _, body) = problem(form.validate().unwrap_err().into()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["detail"], "One or more fields are invalid");
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, ["alpha", "mid", "zeta"]);
+        assert_eq!(body["errors"][0]["code"], "range");
+    }
+}
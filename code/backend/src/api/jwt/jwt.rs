@@ -1,12 +1,46 @@
+use actix::Addr;
 use actix_web::{HttpRequest, http::header::HeaderMap, dev::ServiceRequest};
 use jsonwebtoken::{encode, decode, Header, Algorithm, EncodingKey, DecodingKey, Validation, TokenData};
 use serde::{Serialize, Deserialize};
+use std::sync::OnceLock;
 use std::time::{SystemTime, Duration};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::api::error::ApiError;
+use crate::api::redis::{GetCommand, RedisActor, SetCommand};
+use crate::db::model::user::Role;
+
+const TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 10); // 10 days
+
+static KEYS: OnceLock<(EncodingKey, DecodingKey)> = OnceLock::new();
+
+/// Sets the key (`auth.jwt_secret`) tokens are signed and verified with.
+/// Called once at startup, later calls keep the first key.
+pub fn init(secret: &str) {
+    KEYS.get_or_init(|| (EncodingKey::from_secret(secret.as_bytes()), DecodingKey::from_secret(secret.as_bytes())));
+}
+
+fn keys() -> &'static (EncodingKey, DecodingKey) {
+    KEYS.get().expect("jwt::init is called at startup")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Issued at. Tokens from before this field existed count as issued at 0.
+    #[serde(default)]
+    pub iat: usize,
+    /// Tokens without a role belong to plain users.
+    #[serde(default)]
+    pub role: Role,
+}
+
+fn now() -> usize {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as usize
+}
+
+fn revoked_key(sub: &str) -> String {
+    format!("chat:sessions:revoked:{}", sub)
 }
 
 // For generics
@@ -26,20 +60,22 @@ impl RequestHeaders for ServiceRequest {
     }
 }
 
-pub fn create_token(name: &str) -> Result<String, jsonwebtoken::errors::Error> {
-    let expiration = SystemTime::now() + Duration::from_secs(60 * 60 * 24 * 10); // 10 days
+pub fn create_token(name: &str, role: Role) -> Result<String, jsonwebtoken::errors::Error> {
+    let iat = now();
     let claims = Claims {
         sub: name.to_owned(),
-        exp: expiration.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as usize,
+        exp: iat + TOKEN_LIFETIME.as_secs() as usize,
+        iat,
+        role,
     };
-    encode(&Header::default(), &claims, &EncodingKey::from_secret("secret".as_ref()))
+    encode(&Header::default(), &claims, &keys().0)
 }
 
 pub fn decode_token(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    decode::<Claims>(token, &DecodingKey::from_secret("secret".as_ref()), &Validation::new(Algorithm::HS256))
+    decode::<Claims>(token, &keys().1, &Validation::new(Algorithm::HS256))
 }
 
-pub fn verify <R: RequestHeaders>(req: &R)  -> Result<Claims, String>
+pub fn verify <R: RequestHeaders>(req: &R)  -> Result<Claims, ApiError>
 {
     // Extract the token from the Authorization header
     if let Some(auth_header) = req.get_headers().get("Authorization") {
@@ -49,17 +85,31 @@ pub fn verify <R: RequestHeaders>(req: &R)  -> Result<Claims, String>
             if parts.len() == 2 && parts[0] == "Bearer" {
                 let token = parts[1];
                 // Verify the token and decode the user information
-                match self::decode_token(token) {
-                    Ok(user_info) => {
-                        return Ok(user_info.claims);
-                    },
-                    Err(err) => {
-                        // Token is invalid
-                        return Err(err.to_string());
-                    }
-                }
+                return Ok(self::decode_token(token)?.claims);
             }
         }
     }
-    return Err("Header Authorization is not found".to_owned());
-}
\ No newline at end of file
+    Err(ApiError::Unauthorized("Header Authorization is not found".to_owned()))
+}
+
+/// Invalidates every token issued to `sub` so far. The marker expires with
+/// the longest lived of them.
+pub async fn revoke_sessions(redis: &Addr<RedisActor>, sub: &str) -> Result<(), ApiError> {
+    redis
+        .send(SetCommand { key: revoked_key(sub), value: now().to_string(), ex: Some(TOKEN_LIFETIME.as_secs() as usize) })
+        .await??;
+    Ok(())
+}
+
+/// [`verify`], and rejects tokens issued before the sessions of their
+/// subject were revoked.
+pub async fn verify_active<R: RequestHeaders>(req: &R, redis: &Addr<RedisActor>) -> Result<Claims, ApiError> {
+    let claims = verify(req)?;
+    if let Some(revoked_at) = redis.send(GetCommand { key: revoked_key(&claims.sub) }).await?? {
+        // Same second counts as revoked, iat has no finer resolution
+        if revoked_at.parse::<usize>().map(|at| claims.iat <= at).unwrap_or(true) {
+            return Err(ApiError::Unauthorized("The session has been revoked".to_owned()));
+        }
+    }
+    Ok(claims)
+}
@@ -0,0 +1,121 @@
+use actix_cors::Cors;
+
+use crate::settings::CorsSettings;
+
+/// Builds the CORS policy from settings. Only the configured origins are
+/// allowed, unless they contain `*`.
+pub fn cors(settings: &CorsSettings) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(settings.allowed_methods.iter().map(|m| m.as_str()))
+        .allowed_headers(settings.allowed_headers.iter().map(|h| h.as_str()))
+        // Lets browser clients read the correlation id of failed requests
+        .expose_headers(["x-request-id"])
+        .max_age(settings.max_age);
+    /*
+    max_age: once the browser makes a successful preflight request to the server,
+    it can cache the results of that request for this many seconds.
+    Subsequent requests to the same resource within this time frame won't trigger another preflight request.
+    */
+
+    if settings.allowed_origins.iter().any(|o| o == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &settings.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+    if settings.supports_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::{header, Method, StatusCode}, test, web, App, HttpResponse};
+
+    fn settings() -> CorsSettings {
+        CorsSettings {
+            allowed_origins: vec!["http://localhost".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Authorization".to_string(), "Content-Type".to_string()],
+            supports_credentials: true,
+            max_age: 3600,
+        }
+    }
+
+    fn preflight(origin: &str, method: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/sse/publish")
+            .insert_header((header::ORIGIN, origin))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, method))
+    }
+
+    #[actix_web::test]
+    async fn preflight_from_allowed_origin_is_accepted() {
+        let app = test::init_service(
+            App::new()
+                .wrap(cors(&settings()))
+                .route("/api/sse/publish", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, preflight("http://localhost", "POST").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "http://localhost");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+    }
+
+    #[actix_web::test]
+    async fn preflight_from_other_origin_is_rejected() {
+        let app = test::init_service(
+            App::new()
+                .wrap(cors(&settings()))
+                .route("/api/sse/publish", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, preflight("http://evil.example", "POST").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_web::test]
+    async fn preflight_with_disallowed_method_is_rejected() {
+        let app = test::init_service(
+            App::new()
+                .wrap(cors(&settings()))
+                .route("/api/sse/publish", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, preflight("http://localhost", "DELETE").to_request()).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn simple_request_from_other_origin_gets_no_cors_headers() {
+        let app = test::init_service(
+            App::new()
+                .wrap(cors(&settings()))
+                .route("/api/sse/publish", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/sse/publish")
+            .insert_header((header::ORIGIN, "http://evil.example"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+
+        // The browser blocks the response since it is not allowed for this origin
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+}
@@ -2,17 +2,50 @@ use actix_web::{body::EitherBody, dev};
 use actix_service::Service;
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse, Transform},
-    Error, HttpResponse,
+    Error, ResponseError,
 };
 use futures::future::{ok, Ready, LocalBoxFuture};
 
+use std::rc::Rc;
+
+use actix::Addr;
+use actix_web::{web, HttpMessage};
+
+use serde_json::{json, Value};
+
+use crate::api::audit::actor;
+use crate::api::client_ip::client_ip;
+use crate::api::error::ApiError;
 use crate::api::jwt::jwt;
+use crate::api::redis::RedisActor;
+use crate::db::model::user::Role;
+use crate::db::repository::audit_repository::AuditRepository;
+use crate::library::metrics::METRICS;
+
+/// Rejects requests without a valid, unrevoked token and, if a role is
+/// required, tokens of any other role. The [`jwt::Claims`] are left in the
+/// request extensions for handlers (`web::ReqData<Claims>`). Missing roles are
+/// written to the audit log, rejected tokens only logged and counted.
+pub struct JwtMiddleware {
+    role: Option<Role>,
+}
 
-pub struct JwtMiddleware;
+impl JwtMiddleware {
+    /// Any logged in user.
+    #[allow(dead_code)]
+    pub fn authenticated() -> Self {
+        JwtMiddleware { role: None }
+    }
+
+    /// Only users with `role`.
+    pub fn require(role: Role) -> Self {
+        JwtMiddleware { role: Some(role) }
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for JwtMiddleware
     where
-        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
         S::Future: 'static,
         B: 'static,
 {
@@ -23,17 +56,18 @@ impl<S, B> Transform<S, ServiceRequest> for JwtMiddleware
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(JwtMiddlewareService { service })
+        ok(JwtMiddlewareService { service: Rc::new(service), role: self.role })
     }
 }
 
 pub struct JwtMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
+    role: Option<Role>,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -44,27 +78,113 @@ where
     dev::forward_ready!(service);
 
     fn call(&self, request: ServiceRequest) -> Self::Future {
-        let is_logged_in = match jwt::verify(&request) {
-            Ok(_user_info) => true,
-            Err(_) => false
-        };
+        let redis = request.app_data::<web::Data<Addr<RedisActor>>>().cloned();
+        let audit = request.app_data::<web::Data<AuditRepository>>().cloned();
+        let required = self.role;
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let claims = match jwt::verify(&request) {
+                Ok(claims) => claims,
+                Err(err) => {
+                    // A token was sent but is forged, malformed or expired
+                    if let ApiError::Jwt(e) = &err {
+                        let reason = match e.kind() {
+                            jsonwebtoken::errors::ErrorKind::ExpiredSignature => "expired",
+                            _ => "invalid",
+                        };
+                        token_rejected(&request, "anonymous", reason);
+                    }
+                    return Ok(reject(request, err));
+                }
+            };
+            tracing::Span::current().record("user_id", claims.sub.as_str());
+            if let Some(role) = required.filter(|role| *role != claims.role) {
+                record(&audit, &request, &claims.sub, "auth.forbidden", json!({ "role": claims.role, "required": role })).await;
+                let err = ApiError::Forbidden(format!("This API requires the {} role", role.as_str()));
+                return Ok(reject(request, err));
+            }
+            // Revoked sessions are only known to Redis
+            if let Some(redis) = redis {
+                if let Err(err) = jwt::verify_active(&request, &redis).await {
+                    if let ApiError::Unauthorized(_) = &err {
+                        token_rejected(&request, &claims.sub, "revoked");
+                    }
+                    return Ok(reject(request, err));
+                }
+            }
+            request.extensions_mut().insert(claims);
+            // forwarded responses map to "left" body
+            service.call(request).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
 
-        if !is_logged_in {
-            let (request, _pl) = request.into_parts();
+/// Anybody can send tokens, so rejections are only logged and counted: an
+/// audit row each would let them grow the append-only table at will.
+fn token_rejected(request: &ServiceRequest, name: &str, reason: &'static str) {
+    METRICS.auth_tokens_rejected.with_label_values(&[reason]).inc();
+    let ip = client_ip(request.request()).map(|ip| ip.to_string());
+    tracing::warn!(user = name, reason, ip, path = request.path(), "Token rejected");
+}
 
-            let response = HttpResponse::Unauthorized()
-                .finish()
-                // constructed responses map to "right" body
-                .map_into_right_body();
+async fn record(audit: &Option<web::Data<AuditRepository>>, request: &ServiceRequest, name: &str, action: &str, details: Value) {
+    if let Some(audit) = audit {
+        let target = request.path().to_string();
+        audit.record_or_log(&actor(request.request(), name), action, Some(&target), details).await;
+    }
+}
 
-            return Box::pin(async { Ok(ServiceResponse::new(request, response)) });
-        }
+fn reject<B>(request: ServiceRequest, err: ApiError) -> ServiceResponse<EitherBody<B>> {
+    let (request, _pl) = request.into_parts();
+    let response = err
+        .error_response()
+        // constructed responses map to "right" body
+        .map_into_right_body();
+    ServiceResponse::new(request, response)
+}
 
-        let res = self.service.call(request);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpResponse};
 
-        Box::pin(async move {
-            // forwarded responses map to "left" body
-            res.await.map(ServiceResponse::map_into_left_body)
-        })
+    async fn whoami(claims: web::ReqData<jwt::Claims>) -> HttpResponse {
+        HttpResponse::Ok().body(claims.sub.clone())
+    }
+
+    fn bearer(name: &str, role: Role) -> (&'static str, String) {
+        jwt::init("test-secret-of-at-least-thirty-two-bytes");
+        ("Authorization", format!("Bearer {}", jwt::create_token(name, role).unwrap()))
+    }
+
+    #[actix_web::test]
+    async fn required_role_is_enforced() {
+        let app = test::init_service(
+            App::new().wrap(JwtMiddleware::require(Role::Admin)).route("/", web::get().to(whoami))
+        ).await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.status(), 401);
+
+        let req = test::TestRequest::get().uri("/").insert_header(bearer("alice", Role::User)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let req = test::TestRequest::get().uri("/").insert_header(bearer("root", Role::Admin)).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(test::read_body(res).await, "root");
+
+        // Signed with any other key, such as the old hardcoded one
+        let claims = jwt::Claims { sub: "root".to_string(), exp: usize::MAX, iat: 0, role: Role::Admin };
+        let forged = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        let rejected = METRICS.auth_tokens_rejected.with_label_values(&["invalid"]).get();
+        let req = test::TestRequest::get().uri("/").insert_header(("Authorization", format!("Bearer {}", forged))).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+        assert!(METRICS.auth_tokens_rejected.with_label_values(&["invalid"]).get() > rejected);
     }
 }
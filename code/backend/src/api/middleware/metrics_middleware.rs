@@ -0,0 +1,92 @@
+use std::time::Instant;
+
+use actix_service::Service;
+use actix_web::{
+    dev::{self, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::{ok, Ready, LocalBoxFuture};
+
+use crate::library::metrics::METRICS;
+
+/// Counts requests and their latency per route pattern (`/api/users/{user_id}`),
+/// so ids in paths do not create a series each.
+pub struct MetricsMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for MetricsMiddleware
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = MetricsMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddlewareService { service })
+    }
+}
+
+pub struct MetricsMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let method = request.method().to_string();
+        let started = Instant::now();
+        let res = self.service.call(request);
+
+        Box::pin(async move {
+            let res = res.await?;
+            // Unknown paths share one series
+            let route = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+            METRICS
+                .http_requests
+                .with_label_values(&[method.as_str(), route.as_str(), res.status().as_str()])
+                .inc();
+            METRICS
+                .http_duration
+                .with_label_values(&[method.as_str(), route.as_str()])
+                .observe(started.elapsed().as_secs_f64());
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn labels_requests_by_route_pattern() {
+        let app = test::init_service(
+            App::new()
+                .wrap(MetricsMiddleware)
+                .route("/metrics-test/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for id in 1..=3 {
+            let req = test::TestRequest::get().uri(&format!("/metrics-test/{}", id)).to_request();
+            test::call_service(&app, req).await;
+        }
+        let count = METRICS.http_requests.with_label_values(&["GET", "/metrics-test/{id}", "200"]).get();
+        assert_eq!(count, 3);
+        assert!(METRICS.render().contains("chat_http_requests_total{method=\"GET\",route=\"/metrics-test/{id}\",status=\"200\"} 3"));
+    }
+}
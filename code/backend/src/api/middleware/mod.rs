@@ -1 +1,4 @@
-pub mod jwt_middleware;
\ No newline at end of file
+pub mod cors_middleware;
+pub mod jwt_middleware;
+pub mod metrics_middleware;
+pub mod request_id_middleware;
\ No newline at end of file
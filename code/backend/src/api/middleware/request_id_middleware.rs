@@ -0,0 +1,136 @@
+use std::time::Instant;
+
+use actix_service::Service;
+use actix_web::{
+    dev::{self, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error, HttpMessage, HttpRequest,
+};
+use futures::future::{ok, Ready, LocalBoxFuture};
+use tracing::{field, Instrument};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Correlation id of the current request, taken from `X-Request-Id` when the
+/// client (or a proxy) sent a sane one, otherwise generated.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// The id set by [`RequestIdMiddleware`] for this request, if it ran.
+pub fn request_id(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<RequestId>().map(|id| id.0.clone())
+}
+
+fn new_request_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Runs every request in a `request` span carrying its id, echoes the id in
+/// the response and logs the outcome. Handlers fill in `user_id` and
+/// `room_id` with `Span::current().record(..)`.
+pub struct RequestIdMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddleware
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestIdMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdMiddlewareService { service })
+    }
+}
+
+pub struct RequestIdMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let request_id = request
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| is_valid(id))
+            .map(str::to_string)
+            .unwrap_or_else(new_request_id);
+        request.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %request.method(),
+            path = %request.path(),
+            user_id = field::Empty,
+            room_id = field::Empty,
+        );
+        let started = Instant::now();
+        let res = span.in_scope(|| self.service.call(request));
+
+        Box::pin(
+            async move {
+                let mut res = res.await?;
+                tracing::info!(
+                    status = res.status().as_u16(),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "request completed"
+                );
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    async fn echo(req: HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(request_id(&req).unwrap_or_default())
+    }
+
+    #[actix_web::test]
+    async fn keeps_valid_client_id() {
+        let app = test::init_service(App::new().wrap(RequestIdMiddleware).route("/", web::get().to(echo))).await;
+        let req = test::TestRequest::get().uri("/").insert_header(("X-Request-Id", "abc-123")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(&REQUEST_ID_HEADER).unwrap(), "abc-123");
+        assert_eq!(test::read_body(res).await, "abc-123");
+    }
+
+    #[actix_web::test]
+    async fn replaces_missing_or_invalid_id() {
+        let app = test::init_service(App::new().wrap(RequestIdMiddleware).route("/", web::get().to(echo))).await;
+        let req = test::TestRequest::get().uri("/").insert_header(("X-Request-Id", "bad id\"")).to_request();
+        let res = test::call_service(&app, req).await;
+        let id = res.headers().get(&REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        assert_eq!(test::read_body(res).await, id.as_str());
+    }
+}
@@ -1,6 +1,10 @@
 pub mod api_handler;
+pub mod audit;
+pub mod broadcaster;
+pub mod client_ip;
+pub mod error;
 pub mod middleware;
 pub mod redis;
-mod jwt;
+pub mod jwt;
 mod controller;
-mod requests;
+pub mod requests;
@@ -1,7 +1,8 @@
-use actix_web::{web, HttpResponse, Responder};
 use actix::prelude::*;
 use redis::{cluster_async::ClusterConnection, cluster::ClusterClient, cluster::ClusterConfig};
 
+use crate::library::metrics::time_redis;
+
 pub struct RedisActor {
     conn: ClusterConnection,
 }
@@ -20,7 +21,7 @@ impl RedisActor {
 
 #[derive(Message, Debug)]
 #[rtype(result = "Result<Option<String>, redis::RedisError>")]
-struct InfoCommand;
+pub struct InfoCommand;
 
 #[derive(Message, Debug)]
 #[rtype(result = "Result<Option<String>, redis::RedisError>")]
@@ -28,18 +29,67 @@ pub struct GetCommand {
     pub key: String,
 }
 
+#[derive(Message, Debug)]
+#[rtype(result = "Result<Option<String>, redis::RedisError>")]
+pub struct SetCommand {
+    pub key: String,
+    pub value: String,
+    pub ex: Option<usize>, // Optional expiration time in seconds
+}
+
 #[derive(Message, Debug)]
 #[rtype(result = "Result<bool, redis::RedisError>")]
-pub struct HasCommand {
+pub struct SetNxCommand {
     pub key: String,
+    pub value: String,
+    pub ex: usize, // Expiration time in seconds
 }
 
 #[derive(Message, Debug)]
-#[rtype(result = "Result<Option<String>, redis::RedisError>")]
-pub struct SetCommand {
+#[rtype(result = "Result<usize, redis::RedisError>")]
+pub struct PublishCommand {
+    pub channel: String,
+    pub payload: String,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Result<usize, redis::RedisError>")]
+pub struct SAddCommand {
+    pub key: String,
+    pub member: String,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Result<(), redis::RedisError>")]
+pub struct LPushCommand {
     pub key: String,
     pub value: String,
-    pub ex: Option<usize>, // Optional expiration time in seconds
+    pub max_len: usize,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Result<Vec<String>, redis::RedisError>")]
+pub struct LRangeCommand {
+    pub key: String,
+    pub start: isize,
+    pub stop: isize,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Result<String, redis::RedisError>")]
+pub struct XAddCommand {
+    pub key: String,
+    pub maxlen: usize,
+    pub field: String,
+    pub value: String,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Result<usize, redis::RedisError>")]
+pub struct XAckCommand {
+    pub key: String,
+    pub group: String,
+    pub id: String,
 }
 
 impl Handler<InfoCommand> for RedisActor {
@@ -49,13 +99,26 @@ impl Handler<InfoCommand> for RedisActor {
         let mut con = self.conn.clone();
         let cmd = redis::cmd("INFO");
         let fut = async move {
-            cmd.query_async(&mut con).await
+            let value: redis::Value = cmd.query_async(&mut con).await?;
+            match value {
+                // A cluster answers with one reply per node, keyed by address
+                redis::Value::Map(nodes) => {
+                    let mut info = String::new();
+                    for (addr, reply) in nodes {
+                        let addr: String = redis::from_redis_value(&addr)?;
+                        let reply: String = redis::from_redis_value(&reply)?;
+                        info.push_str(&format!("# Node {}\n{}\n", addr, reply));
+                    }
+                    Ok(Some(info))
+                }
+                other => redis::from_redis_value(&other),
+            }
         };
-        Box::pin(fut)
+        Box::pin(time_redis("INFO", fut))
     }
 }
 
-impl<'a> Handler<GetCommand> for RedisActor {
+impl Handler<GetCommand> for RedisActor {
     type Result = ResponseFuture<Result<Option<String>, redis::RedisError>>;
 
     fn handle(&mut self, msg: GetCommand, _: &mut Self::Context) -> Self::Result {
@@ -69,27 +132,7 @@ impl<'a> Handler<GetCommand> for RedisActor {
                 .await
         };
 
-        Box::pin(fut)
-    }
-}
-
-// Implement the `HasCommand` to check if a key exists
-impl Handler<HasCommand> for RedisActor {
-    type Result = ResponseFuture<Result<bool, redis::RedisError>>;
-
-    fn handle(&mut self, msg: HasCommand, _: &mut Self::Context) -> Self::Result {
-        let mut con = self.conn.clone();
-        let key = msg.key.to_string();
-
-        let fut = async move {
-            let exists: bool = redis::cmd("EXISTS")
-                .arg(key)
-                .query_async(&mut con)
-                .await?;
-            Ok(exists)
-        };
-
-        Box::pin(fut)
+        Box::pin(time_redis("GET", fut))
     }
 }
 
@@ -122,63 +165,160 @@ impl Handler<SetCommand> for RedisActor {
             }
         };
 
-        Box::pin(fut)
+        Box::pin(time_redis("SET", fut))
     }
 }
 
-impl Actor for RedisActor {
-    type Context = Context<Self>;
+// Set a key only if it does not exist yet. Returns whether it was set.
+impl Handler<SetNxCommand> for RedisActor {
+    type Result = ResponseFuture<Result<bool, redis::RedisError>>;
+
+    fn handle(&mut self, msg: SetNxCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            let set: Option<String> = redis::cmd("SET")
+                .arg(msg.key)
+                .arg(msg.value)
+                .arg("NX")
+                .arg("EX")
+                .arg(msg.ex)
+                .query_async(&mut con)
+                .await?;
+            Ok(set.is_some())
+        };
+
+        Box::pin(time_redis("SETNX", fut))
+    }
 }
 
-pub async fn _info(redis: web::Data<Addr<RedisActor>>) -> impl Responder {
-    let res = redis.send(InfoCommand).await.unwrap().unwrap().unwrap();
-    HttpResponse::Ok().body(res)
-}
-
-pub async fn get<'a>(
-    redis: &'a web::Data<Addr<RedisActor>>,
-    key: &'a str // Take ownership of the key
-) -> Result<String, Box<dyn std::error::Error>> {
-    // Send a GetCommand to the RedisActor, move the key into the command
-    match redis.send(GetCommand { key: key.to_string() }).await {
-        // If the message was sent successfully and the response is Ok
-        Ok(Ok(Some(value))) => Ok(value), // Return the value fetched from Redis
-        // If the response contains an error from Redis
-        Ok(Ok(None)) => Err("Key not found in Redis".into()), // Handle key not found
-        Ok(Err(redis_error)) => Err(Box::new(redis_error)), // Handle Redis errors
-        // If the actor communication fails
-        Err(mailbox_error) => Err(Box::new(mailbox_error)), // Handle Actix mailbox errors
+// Publish a payload to a pub/sub channel. Returns the number of receivers.
+impl Handler<PublishCommand> for RedisActor {
+    type Result = ResponseFuture<Result<usize, redis::RedisError>>;
+
+    fn handle(&mut self, msg: PublishCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            redis::cmd("PUBLISH")
+                .arg(msg.channel)
+                .arg(msg.payload)
+                .query_async(&mut con)
+                .await
+        };
+
+        Box::pin(time_redis("PUBLISH", fut))
     }
 }
 
-pub async fn has<'a>(
-    redis: &'a web::Data<Addr<RedisActor>>,
-    key: &'a str // Take ownership of the key
-) -> Result<bool, Box<dyn std::error::Error>> {
-    // Send a GetCommand to the RedisActor, move the key into the command
-    match redis.send(HasCommand { key: key.to_string() }).await {
-        // If the message was sent successfully and the response is Ok
-        Ok(Ok(true)) => Ok(true), // Return the value fetched from Redis
-        // If the response contains an error from Redis
-        Ok(Ok(false)) => Ok(false), // Handle key not found
-        Ok(Err(redis_error)) => Err(Box::new(redis_error)), // Handle Redis errors
-        // If the actor communication fails
-        Err(mailbox_error) => Err(Box::new(mailbox_error)), // Handle Actix mailbox errors
+// Add a member to a set. Returns the number of members actually added.
+impl Handler<SAddCommand> for RedisActor {
+    type Result = ResponseFuture<Result<usize, redis::RedisError>>;
+
+    fn handle(&mut self, msg: SAddCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            redis::cmd("SADD")
+                .arg(msg.key)
+                .arg(msg.member)
+                .query_async(&mut con)
+                .await
+        };
+
+        Box::pin(time_redis("SADD", fut))
     }
 }
 
-pub async fn setex<'a> (
-    redis: &'a web::Data<Addr<RedisActor>>,
-    key: &'a str,
-    value: &'a str,
-    ex: Option<usize>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Send a GetCommand to the RedisActor, move the key into the command
-    match redis.send(SetCommand { key: key.to_string(), value: value.to_string(), ex }).await {
-        // If the message was sent successfully and the response is Ok
-        Ok(Ok(_)) => Ok(()), // Return the value fetched from Redis
-        Ok(Err(redis_error)) => Err(Box::new(redis_error)), // Handle Redis errors
-        // If the actor communication fails
-        Err(mailbox_error) => Err(Box::new(mailbox_error)), // Handle Actix mailbox errors
+// Push to the head of a list and trim it to `max_len` entries.
+impl Handler<LPushCommand> for RedisActor {
+    type Result = ResponseFuture<Result<(), redis::RedisError>>;
+
+    fn handle(&mut self, msg: LPushCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            redis::cmd("LPUSH")
+                .arg(&msg.key)
+                .arg(msg.value)
+                .query_async::<()>(&mut con)
+                .await?;
+            redis::cmd("LTRIM")
+                .arg(&msg.key)
+                .arg(0)
+                .arg(msg.max_len.saturating_sub(1))
+                .query_async(&mut con)
+                .await
+        };
+
+        Box::pin(time_redis("LPUSH", fut))
     }
-}
\ No newline at end of file
+}
+
+// Read a range of a list, `stop` is inclusive and -1 means the end.
+impl Handler<LRangeCommand> for RedisActor {
+    type Result = ResponseFuture<Result<Vec<String>, redis::RedisError>>;
+
+    fn handle(&mut self, msg: LRangeCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            redis::cmd("LRANGE")
+                .arg(msg.key)
+                .arg(msg.start)
+                .arg(msg.stop)
+                .query_async(&mut con)
+                .await
+        };
+
+        Box::pin(time_redis("LRANGE", fut))
+    }
+}
+
+// Append an entry to a stream, trimming it to about `maxlen` entries.
+impl Handler<XAddCommand> for RedisActor {
+    type Result = ResponseFuture<Result<String, redis::RedisError>>;
+
+    fn handle(&mut self, msg: XAddCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            redis::cmd("XADD")
+                .arg(msg.key)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(msg.maxlen)
+                .arg("*")
+                .arg(msg.field)
+                .arg(msg.value)
+                .query_async(&mut con)
+                .await
+        };
+
+        Box::pin(time_redis("XADD", fut))
+    }
+}
+
+// Acknowledge a stream entry for a consumer group.
+impl Handler<XAckCommand> for RedisActor {
+    type Result = ResponseFuture<Result<usize, redis::RedisError>>;
+
+    fn handle(&mut self, msg: XAckCommand, _: &mut Self::Context) -> Self::Result {
+        let mut con = self.conn.clone();
+
+        let fut = async move {
+            redis::cmd("XACK")
+                .arg(msg.key)
+                .arg(msg.group)
+                .arg(msg.id)
+                .query_async(&mut con)
+                .await
+        };
+
+        Box::pin(time_redis("XACK", fut))
+    }
+}
+
+impl Actor for RedisActor {
+    type Context = Context<Self>;
+}
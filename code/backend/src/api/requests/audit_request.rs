@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use validator::{Validate, ValidationError};
+
+/// Query of `GET /api/admin/audit`. Times are RFC 3339, `to` is exclusive.
+#[derive(Serialize, Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_range"))]
+pub struct AuditRequest {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Only events older than this id, pass the last id of a page for the next one.
+    #[validate(range(min = 1, message = "must be positive"))]
+    pub before_id: Option<i64>,
+    #[validate(range(min = 1, max = 500, message = "must be 1 to 500"))]
+    pub limit: Option<i64>,
+}
+
+impl AuditRequest {
+    pub const DEFAULT_LIMIT: i64 = 100;
+}
+
+fn validate_range(req: &AuditRequest) -> Result<(), ValidationError> {
+    match (req.from, req.to) {
+        (Some(from), Some(to)) if from >= to => {
+            Err(ValidationError::new("invalid_range").with_message("from must be before to".into()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(query: &str) -> AuditRequest {
+        actix_web::web::Query::<AuditRequest>::from_query(query).unwrap().into_inner()
+    }
+
+    #[test]
+    fn accepts_filters() {
+        let req = request("actor=alice&action=auth.login&from=2026-01-01T00:00:00Z&to=2026-02-01T00:00:00%2B09:00&limit=10");
+        assert!(req.validate().is_ok());
+        assert_eq!(req.actor.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn rejects_reversed_range_and_limit() {
+        assert!(request("from=2026-02-01T00:00:00Z&to=2026-01-01T00:00:00Z").validate().is_err());
+        assert!(request("limit=0").validate().is_err());
+        assert!(request("limit=501").validate().is_err());
+    }
+}
@@ -0,0 +1,8 @@
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct EventsRequest {
+    #[validate(range(min = 1, message = "must be a positive room id"))]
+    pub room_id: i32,
+}
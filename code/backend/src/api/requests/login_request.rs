@@ -1,7 +1,54 @@
 use serde::{Serialize, Deserialize};
+use validator::{Validate, ValidationError};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct LoginRequest {
+    #[validate(
+        length(min = 1, max = 50, message = "must be 1 to 50 characters"),
+        custom(function = "validate_name")
+    )]
     pub name: String,
+    #[validate(custom(function = "validate_password"))]
     pub password: String,
-}
\ No newline at end of file
+}
+
+/// Longest password in bytes, bcrypt ignores everything after them.
+const MAX_PASSWORD_BYTES: usize = 72;
+
+// Counted in bytes, not characters: a 30 character password may well be longer
+fn validate_password(password: &str) -> Result<(), ValidationError> {
+    if (1..=MAX_PASSWORD_BYTES).contains(&password.len()) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("length").with_message("must be 1 to 72 bytes".into()))
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+    if name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_characters")
+            .with_message("may only contain letters, digits, '_', '-' and '.'".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(password: &str) -> LoginRequest {
+        LoginRequest { name: "alice".to_string(), password: password.to_string() }
+    }
+
+    #[test]
+    fn password_length_is_counted_in_bytes() {
+        assert!(request(&"a".repeat(72)).validate().is_ok());
+        assert!(request(&"é".repeat(36)).validate().is_ok());
+        // 37 characters, but 74 bytes that bcrypt would cut to 72
+        let errors = request(&"é".repeat(37)).validate().unwrap_err();
+        assert_eq!(errors.field_errors()["password"][0].code, "length");
+        assert!(request("").validate().is_err());
+        assert!(request(&"a".repeat(73)).validate().is_err());
+    }
+}
@@ -1,2 +1,8 @@
+pub mod audit_request;
+pub mod events_request;
 pub mod login_request;
-pub mod publish_request;
\ No newline at end of file
+pub mod notifications_request;
+pub mod publish_request;
+pub mod rename_user_request;
+pub mod update_profile_request;
+pub mod users_request;
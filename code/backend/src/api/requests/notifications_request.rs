@@ -0,0 +1,25 @@
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+
+/// Query of `GET /api/notifications`.
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct NotificationsRequest {
+    /// Only unread notifications.
+    #[serde(default)]
+    pub unread: bool,
+    /// Only notifications older than this id, pass the last id of a page for the next one.
+    #[validate(range(min = 1, message = "must be positive"))]
+    pub before_id: Option<i64>,
+    #[validate(range(min = 1, max = 100, message = "must be 1 to 100"))]
+    pub limit: Option<i64>,
+}
+
+impl NotificationsRequest {
+    pub const DEFAULT_LIMIT: i64 = 20;
+}
+
+/// Body of `PATCH /api/notifications/{id}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateNotificationRequest {
+    pub read: bool,
+}
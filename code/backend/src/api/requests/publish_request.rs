@@ -1,6 +1,54 @@
 use serde::{Serialize, Deserialize};
+use validator::{Validate, ValidationError};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct PublishRequest {
+    #[validate(range(min = 1, message = "must be a positive room id"))]
+    pub room_id: i32,
+    #[validate(
+        length(min = 1, max = 2000, message = "must be 1 to 2000 characters"),
+        custom(function = "validate_msg")
+    )]
     pub msg: String,
-}
\ No newline at end of file
+}
+
+fn validate_msg(msg: &str) -> Result<(), ValidationError> {
+    if msg.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be blank".into()));
+    }
+    // Line breaks and tabs are fine, other control characters would break the SSE framing
+    if msg.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+        return Err(ValidationError::new("invalid_characters")
+            .with_message("must not contain control characters".into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(room_id: i32, msg: &str) -> PublishRequest {
+        PublishRequest { room_id, msg: msg.to_string() }
+    }
+
+    #[test]
+    fn accepts_regular_message() {
+        assert!(request(1, "hello\nworld").validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_blank_and_oversized_messages() {
+        assert!(request(1, "").validate().is_err());
+        assert!(request(1, "   ").validate().is_err());
+        assert!(request(1, &"a".repeat(2001)).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_control_characters_and_bad_room() {
+        let errors = request(0, "a\rb").validate().unwrap_err();
+        let fields = errors.field_errors();
+        assert!(fields.contains_key("room_id"));
+        assert_eq!(fields["msg"][0].code, "invalid_characters");
+    }
+}
@@ -0,0 +1,12 @@
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct RenameUserRequest {
+    // Same rules as at login, so the user can still log in
+    #[validate(
+        length(min = 1, max = 50, message = "must be 1 to 50 characters"),
+        custom(function = "crate::api::requests::login_request::validate_name")
+    )]
+    pub name: String,
+}
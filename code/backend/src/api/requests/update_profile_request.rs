@@ -0,0 +1,97 @@
+use serde::{Serialize, Deserialize};
+use validator::{Validate, ValidationError};
+
+use crate::db::model::user::ProfileChanges;
+
+/// Body of `PATCH /api/users/me`. Missing fields are left as they are, an
+/// empty string clears the field.
+#[derive(Serialize, Deserialize, Debug, Validate, Default)]
+pub struct UpdateProfileRequest {
+    #[validate(
+        length(max = 50, message = "must be at most 50 characters"),
+        custom(function = "validate_single_line")
+    )]
+    pub display_name: Option<String>,
+    #[validate(
+        length(max = 2048, message = "must be at most 2048 characters"),
+        custom(function = "validate_avatar_url")
+    )]
+    pub avatar_url: Option<String>,
+    #[validate(
+        length(max = 500, message = "must be at most 500 characters"),
+        custom(function = "validate_text")
+    )]
+    pub bio: Option<String>,
+    #[validate(
+        length(max = 100, message = "must be at most 100 characters"),
+        custom(function = "validate_single_line")
+    )]
+    pub status_message: Option<String>,
+}
+
+impl UpdateProfileRequest {
+    pub fn changes(&self) -> ProfileChanges<'_> {
+        ProfileChanges {
+            display_name: self.display_name.as_deref(),
+            avatar_url: self.avatar_url.as_deref(),
+            bio: self.bio.as_deref(),
+            status_message: self.status_message.as_deref(),
+        }
+    }
+}
+
+fn control_characters() -> ValidationError {
+    ValidationError::new("invalid_characters").with_message("must not contain control characters".into())
+}
+
+fn validate_single_line(value: &str) -> Result<(), ValidationError> {
+    if value.chars().any(char::is_control) {
+        return Err(control_characters());
+    }
+    Ok(())
+}
+
+// Line breaks are fine in a bio
+fn validate_text(value: &str) -> Result<(), ValidationError> {
+    if value.chars().any(|c| c.is_control() && c != '\n') {
+        return Err(control_characters());
+    }
+    Ok(())
+}
+
+// Clients put it in an <img src>, so nothing but http(s)
+fn validate_avatar_url(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match reqwest::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        _ => Err(ValidationError::new("invalid_url").with_message("must be an http or https URL".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_partial_updates_and_clearing() {
+        let req = UpdateProfileRequest {
+            display_name: Some("Alice A.".to_string()),
+            avatar_url: Some(String::new()),
+            bio: Some("line one\nline two".to_string()),
+            ..Default::default()
+        };
+        assert!(req.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_urls_and_control_characters() {
+        for url in ["javascript:alert(1)", "data:image/png;base64,AAAA", "not a url"] {
+            let req = UpdateProfileRequest { avatar_url: Some(url.to_string()), ..Default::default() };
+            assert!(req.validate().is_err(), "{}", url);
+        }
+        let req = UpdateProfileRequest { status_message: Some("away\nback soon".to_string()), ..Default::default() };
+        assert!(req.validate().is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+
+use crate::db::repository::user_repository::UserSort;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query of `GET /api/users`.
+#[derive(Deserialize, Debug, Validate, Default)]
+pub struct UsersRequest {
+    /// Name or display name, matched by prefix or similarity.
+    #[validate(length(min = 1, max = 50, message = "must be 1 to 50 characters"))]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: UserSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[validate(range(min = 1, message = "must be positive"))]
+    pub page: Option<i64>,
+    #[validate(range(min = 1, max = 100, message = "must be 1 to 100"))]
+    pub per_page: Option<i64>,
+    /// Return the full `User` instead of `{id, name}`.
+    #[serde(default)]
+    pub full: bool,
+}
+
+impl UsersRequest {
+    pub const DEFAULT_PER_PAGE: i64 = 20;
+
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1)
+    }
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(Self::DEFAULT_PER_PAGE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Query;
+
+    fn request(query: &str) -> Result<UsersRequest, String> {
+        Query::<UsersRequest>::from_query(query).map(Query::into_inner).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn defaults() {
+        let req = request("").unwrap();
+        assert!(req.validate().is_ok());
+        assert_eq!((req.page(), req.per_page(), req.sort, req.order, req.full), (1, 20, UserSort::Relevance, SortOrder::Asc, false));
+    }
+
+    #[test]
+    fn parses_and_validates() {
+        let req = request("q=ali&sort=created_at&order=desc&page=2&per_page=50&full=true").unwrap();
+        assert!(req.validate().is_ok());
+        assert_eq!((req.sort, req.order, req.page(), req.full), (UserSort::CreatedAt, SortOrder::Desc, 2, true));
+
+        assert!(request("sort=password").is_err());
+        assert!(request("per_page=101").unwrap().validate().is_err());
+        assert!(request("page=0").unwrap().validate().is_err());
+    }
+}
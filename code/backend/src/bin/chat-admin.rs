@@ -0,0 +1,288 @@
+//! Operational tasks against the same database, Redis and message bus as the
+//! API server. Run `chat-admin --help` for the commands.
+use std::io::BufRead;
+use std::process::ExitCode;
+
+use actix::prelude::*;
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use rand::distr::{Alphanumeric, SampleString};
+use serde_json::json;
+use validator::Validate;
+
+use chat_sample::api::jwt::jwt;
+use chat_sample::api::redis::RedisActor;
+use chat_sample::api::requests::login_request::LoginRequest;
+use chat_sample::bus::dead_letter::DeadLetterSink;
+use chat_sample::bus::{self, new_message_id, BusMessage};
+use chat_sample::db;
+use chat_sample::db::model::audit_event::Actor as AuditActor;
+use chat_sample::db::model::user::Role;
+use chat_sample::db::repository::audit_repository::AuditRepository;
+use chat_sample::db::repository::room_repository::RoomRepository;
+use chat_sample::db::repository::user_repository::UserDataRepository;
+use chat_sample::settings::{BusKind, Settings};
+
+#[derive(Parser)]
+#[command(name = "chat-admin", about = "Administration of the chat service")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage users
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Inspect rooms
+    #[command(subcommand)]
+    Room(RoomCommand),
+    /// Log a user out everywhere by invalidating their tokens
+    RevokeSessions { name: String },
+    /// Insert the development data (services, rooms, users and memberships)
+    Seed,
+    /// Publish messages into a room, one per line of FILE (stdin by default)
+    Replay {
+        room_id: i32,
+        file: Option<String>,
+        /// Replay the newest dead-lettered messages instead of FILE
+        #[arg(long, conflicts_with = "file")]
+        dead_letter: bool,
+        /// How many dead-lettered messages to replay
+        #[arg(long, default_value_t = 100, requires = "dead_letter")]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// List users
+    List,
+    /// Create a user. A random password is generated and printed if none is given
+    Create {
+        name: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Reset the password of a user and revoke their sessions
+    ResetPassword {
+        name: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Forbid a user to log in and revoke their sessions
+    Disable { name: String },
+    /// Allow a disabled user to log in again
+    Enable { name: String },
+    /// Change the role of a user (user or admin) and revoke their sessions
+    SetRole { name: String, role: Role },
+}
+
+#[derive(Subcommand)]
+enum RoomCommand {
+    /// List rooms
+    List,
+    /// List the members of a room
+    Members { room_id: i32 },
+}
+
+#[actix_web::main]
+async fn main() -> ExitCode {
+    dotenv().ok();
+    match run(Cli::parse().command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::User(command) => user(command).await,
+        Command::Room(command) => room(command).await,
+        Command::RevokeSessions { name } => {
+            let settings = settings()?;
+            let redis = redis(&settings).await;
+            jwt::revoke_sessions(&redis, &name).await.map_err(|e| e.to_string())?;
+            println!("Revoked the sessions of {}", name);
+            Ok(())
+        }
+        Command::Seed => {
+            let pool = pool().await?;
+            let summary = db::seed::run(&pool).await.map_err(|e| e.to_string())?;
+            println!(
+                "Inserted {} services, {} rooms, {} users (password '{}') and {} memberships",
+                summary.services, summary.rooms, summary.users, db::seed::PASSWORD, summary.memberships
+            );
+            Ok(())
+        }
+        Command::Replay { room_id, file, dead_letter, limit } => replay(room_id, file, dead_letter, limit).await,
+    }
+}
+
+async fn user(command: UserCommand) -> Result<(), String> {
+    let pool = pool().await?;
+    let repo = UserDataRepository::new(pool.clone());
+    let audit = AuditRepository::new(pool);
+    // Same audit trail as the admin API
+    let record = |action: &'static str, user_id: i32, details: serde_json::Value| {
+        let audit = audit.clone();
+        async move {
+            audit
+                .record(&AuditActor::system("chat-admin"), action, Some(&format!("user:{}", user_id)), details)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    };
+    match command {
+        UserCommand::List => {
+            for user in repo.list().await.map_err(|e| e.to_string())? {
+                println!("{}\t{}", user.id, user.name);
+            }
+        }
+        UserCommand::Create { name, password } => {
+            let (password, generated) = password_or_random(password);
+            // Same rules as the login request, so the user can actually log in
+            LoginRequest { name: name.clone(), password: password.clone() }
+                .validate()
+                .map_err(|e| e.to_string())?;
+            if repo.find_by_name(&name).await.map_err(|e| e.to_string())?.is_some() {
+                return Err(format!("user {} already exists", name));
+            }
+            let user = repo.create(&name, &hash(&password)?).await.map_err(|e| e.to_string())?;
+            record("user.create", user.id, json!({ "name": user.name })).await?;
+            println!("Created user {} ({})", user.name, user.id);
+            if generated {
+                println!("Password: {}", password);
+            }
+        }
+        UserCommand::ResetPassword { name, password } => {
+            let (password, generated) = password_or_random(password);
+            LoginRequest { name: name.clone(), password: password.clone() }
+                .validate()
+                .map_err(|e| e.to_string())?;
+            let user = repo.set_password(&name, &hash(&password)?)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("user {} not found", name))?;
+            revoke(&name).await?;
+            record("user.reset_password", user.id, json!({ "name": name })).await?;
+            println!("Reset the password of {} and revoked their sessions", name);
+            if generated {
+                println!("Password: {}", password);
+            }
+        }
+        UserCommand::Disable { name } => {
+            repo.set_disabled(&name, true, &AuditActor::system("chat-admin"))
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("user {} not found", name))?;
+            revoke(&name).await?;
+            println!("Disabled {} and revoked their sessions", name);
+        }
+        UserCommand::Enable { name } => {
+            repo.set_disabled(&name, false, &AuditActor::system("chat-admin"))
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("user {} not found", name))?;
+            println!("Enabled {}", name);
+        }
+        UserCommand::SetRole { name, role } => {
+            repo.set_role(&name, role, &AuditActor::system("chat-admin"))
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("user {} not found", name))?;
+            // The role is in the token, it only changes with a new login
+            revoke(&name).await?;
+            println!("{} is now {} and has to log in again", name, role.as_str());
+        }
+    }
+    Ok(())
+}
+
+async fn room(command: RoomCommand) -> Result<(), String> {
+    let repo = RoomRepository::new(pool().await?);
+    match command {
+        RoomCommand::List => {
+            for room in repo.list().await.map_err(|e| e.to_string())? {
+                println!("{}\t{}\t{}", room.id, room.name, room.created_at);
+            }
+        }
+        RoomCommand::Members { room_id } => {
+            for user in repo.members(room_id).await.map_err(|e| e.to_string())? {
+                println!("{}\t{}", user.id, user.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn replay(room_id: i32, file: Option<String>, dead_letter: bool, limit: usize) -> Result<(), String> {
+    let settings = settings()?;
+    if settings.bus.kind == BusKind::Memory {
+        return Err("bus.kind is memory, messages published here never reach the server".to_string());
+    }
+    let redis = redis(&settings).await;
+
+    let messages: Vec<Vec<u8>> = if dead_letter {
+        let sink = DeadLetterSink::new(redis.clone(), &settings.bus.dead_letter_key, settings.bus.dead_letter_max_len);
+        // Oldest first, as they were originally sent
+        let mut entries = sink.list(limit).await.map_err(|e| e.to_string())?;
+        entries.reverse();
+        entries.into_iter().map(|entry| entry.data.into_bytes()).collect()
+    } else {
+        let reader: Box<dyn BufRead> = match &file {
+            Some(path) if path != "-" => {
+                Box::new(std::io::BufReader::new(std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?))
+            }
+            _ => Box::new(std::io::stdin().lock()),
+        };
+        let lines: Result<Vec<String>, _> = reader.lines().collect();
+        lines.map_err(|e| e.to_string())?.into_iter().filter(|l| !l.trim().is_empty()).map(String::into_bytes).collect()
+    };
+
+    let message_bus = bus::from_settings(&settings, redis).await.map_err(|e| e.to_string())?;
+    for data in &messages {
+        // New ids: the originals may already be remembered by the deduplicator
+        message_bus
+            .publish(BusMessage::for_room(room_id, data.clone(), new_message_id()))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    message_bus.close().await;
+    println!("Replayed {} messages into room {}", messages.len(), room_id);
+    Ok(())
+}
+
+fn settings() -> Result<Settings, String> {
+    Settings::load().map_err(|e| e.to_string())
+}
+
+async fn pool() -> Result<sqlx::PgPool, String> {
+    let database = Settings::load_database().map_err(|e| e.to_string())?;
+    Ok(db::pool::get_db_pool(&database).await)
+}
+
+async fn redis(settings: &Settings) -> Addr<RedisActor> {
+    RedisActor::new(vec![&settings.redis.url]).await.start()
+}
+
+async fn revoke(name: &str) -> Result<(), String> {
+    let settings = settings()?;
+    let redis = redis(&settings).await;
+    jwt::revoke_sessions(&redis, name).await.map_err(|e| e.to_string())
+}
+
+fn password_or_random(password: Option<String>) -> (String, bool) {
+    match password {
+        Some(password) => (password, false),
+        None => (Alphanumeric.sample_string(&mut rand::rng(), 16), true),
+    }
+}
+
+fn hash(password: &str) -> Result<String, String> {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| e.to_string())
+}
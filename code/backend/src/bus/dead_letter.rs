@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use actix::Addr;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::api::redis::{LPushCommand, LRangeCommand, RedisActor};
+use crate::bus::{BusError, BusMessage};
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    reason: &'a str,
+    received_at: String,
+    // Lossy, the payload is usually not valid UTF-8 when it ends up here.
+    data: String,
+    attributes: &'a HashMap<String, String>,
+}
+
+/// An entry read back from the sink.
+#[derive(Deserialize, Debug)]
+pub struct StoredDeadLetter {
+    pub reason: String,
+    pub received_at: String,
+    pub data: String,
+    pub attributes: HashMap<String, String>,
+}
+
+/// Where the subscriber keeps the messages it could not handle.
+pub trait DeadLetterStore: Send + Sync {
+    fn store<'a>(&'a self, message: &'a BusMessage, reason: &'a str) -> BoxFuture<'a, Result<(), BusError>>;
+}
+
+/// Keeps messages the subscriber could not handle in a capped Redis list
+/// (newest first) so they can be inspected later.
+pub struct DeadLetterSink {
+    redis: Addr<RedisActor>,
+    key: String,
+    max_len: usize,
+}
+
+impl DeadLetterSink {
+    pub fn new(redis: Addr<RedisActor>, key: &str, max_len: usize) -> Self {
+        DeadLetterSink { redis, key: key.to_string(), max_len }
+    }
+
+    /// The newest `count` entries, newest first. Entries are left in place.
+    pub async fn list(&self, count: usize) -> Result<Vec<StoredDeadLetter>, BusError> {
+        let stop = count as isize - 1;
+        let entries = match self.redis.send(LRangeCommand { key: self.key.clone(), start: 0, stop }).await {
+            Ok(Ok(entries)) => entries,
+            Ok(Err(redis_error)) => return Err(BusError::Read(redis_error.to_string())),
+            Err(mailbox_error) => return Err(BusError::Read(mailbox_error.to_string())),
+        };
+        entries
+            .iter()
+            .map(|entry| serde_json::from_str(entry).map_err(|e| BusError::Read(e.to_string())))
+            .collect()
+    }
+}
+
+impl DeadLetterStore for DeadLetterSink {
+    fn store<'a>(&'a self, message: &'a BusMessage, reason: &'a str) -> BoxFuture<'a, Result<(), BusError>> {
+        Box::pin(async move {
+            let entry = DeadLetter {
+                reason,
+                received_at: chrono::Local::now().to_rfc3339(),
+                data: String::from_utf8_lossy(&message.data).into_owned(),
+                attributes: &message.attributes,
+            };
+            let value = serde_json::to_string(&entry).map_err(|e| BusError::Publish(e.to_string()))?;
+            match self.redis.send(LPushCommand { key: self.key.clone(), value, max_len: self.max_len }).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(redis_error)) => Err(BusError::Publish(redis_error.to_string())),
+                Err(mailbox_error) => Err(BusError::Publish(mailbox_error.to_string())),
+            }
+        })
+    }
+}
@@ -0,0 +1,79 @@
+use actix::Addr;
+use futures::future::BoxFuture;
+
+use crate::api::redis::{RedisActor, SetNxCommand};
+
+/// Remembers which messages were delivered already.
+pub trait DedupStore: Send + Sync {
+    /// Whether `message_id` is seen for the first time.
+    fn first_delivery<'a>(&'a self, message_id: &'a str) -> BoxFuture<'a, bool>;
+}
+
+/// Remembers message ids in short-lived Redis keys under a prefix, one per
+/// use: what a pod forwarded to its clients, and what was published with an
+/// `Idempotency-Key`.
+pub struct Deduplicator {
+    redis: Addr<RedisActor>,
+    prefix: String,
+    ttl: usize,
+}
+
+/// Redis key marking `message_id` as seen under `prefix`.
+fn dedup_key(prefix: &str, message_id: &str) -> String {
+    format!("{}:{}", prefix, message_id)
+}
+
+impl Deduplicator {
+    /// Drops messages this pod has already delivered. Pub/Sub is at-least-once
+    /// and clients retry POSTs, so the same `message_id` may arrive more than once.
+    ///
+    /// The keys are scoped to the pod. The bus fans every message out to each
+    /// pod (one subscription or stream group per pod), and every pod has to
+    /// show it to its own clients, so a key shared by all pods would let only
+    /// the first of them deliver it.
+    pub fn for_pod(redis: Addr<RedisActor>, pod_name: &str, ttl: usize) -> Self {
+        Deduplicator { redis, prefix: format!("chat:dedup:{}", pod_name), ttl }
+    }
+
+    /// Messages published with an `Idempotency-Key`, so a retried POST does
+    /// not repeat the side effects of the first one.
+    pub fn for_publishes(redis: Addr<RedisActor>, ttl: usize) -> Self {
+        Deduplicator { redis, prefix: "chat:published".to_string(), ttl }
+    }
+}
+
+impl DedupStore for Deduplicator {
+    /// Whether `message_id` is seen for the first time within the TTL.
+    fn first_delivery<'a>(&'a self, message_id: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            let command = SetNxCommand {
+                key: dedup_key(&self.prefix, message_id),
+                value: "1".to_string(),
+                ex: self.ttl,
+            };
+            match self.redis.send(command).await {
+                Ok(Ok(first)) => first,
+                // Showing a duplicate is better than losing the message
+                Ok(Err(redis_error)) => {
+                    tracing::error!(error = %redis_error, "Dedup check failed");
+                    true
+                }
+                Err(mailbox_error) => {
+                    tracing::error!(error = %mailbox_error, "Dedup check failed");
+                    true
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_scoped_to_their_prefix() {
+        assert_eq!(dedup_key("chat:dedup:chat-0", "m-1"), "chat:dedup:chat-0:m-1");
+        assert_eq!(dedup_key("chat:published", "room-1:k"), "chat:published:room-1:k");
+    }
+}
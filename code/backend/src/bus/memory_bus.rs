@@ -0,0 +1,68 @@
+use futures::future::BoxFuture;
+use futures_util::StreamExt;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::bus::{BusError, BusMessage, Delivery, DeliveryStream, MessageBus};
+use crate::library::metrics::METRICS;
+
+/// In-process bus. Only delivers within this process, so it is meant for
+/// local development and tests.
+pub struct MemoryBus {
+    tx: broadcast::Sender<BusMessage>,
+}
+
+impl MemoryBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _rx) = broadcast::channel(capacity);
+        MemoryBus { tx }
+    }
+}
+
+impl MessageBus for MemoryBus {
+    fn publish(&self, message: BusMessage) -> BoxFuture<'_, Result<(), BusError>> {
+        // Sending only fails when nobody subscribed yet, which is not an error here.
+        let _ = self.tx.send(message);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<DeliveryStream, BusError>> {
+        let rx = self.tx.subscribe();
+        Box::pin(async move {
+            let stream = BroadcastStream::new(rx)
+                .filter_map(|msg| async move {
+                    match msg {
+                        Ok(msg) => Some(Delivery::new(msg, None)),
+                        // The channel only keeps `capacity` messages for a slow subscriber
+                        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                            tracing::warn!(skipped, "Memory bus subscriber lagged, messages lost");
+                            METRICS.bus_dropped.inc_by(skipped);
+                            None
+                        }
+                    }
+                })
+                .boxed();
+            Ok(stream)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lagging_subscribers_count_the_lost_messages() {
+        let bus = MemoryBus::new(2);
+        let mut stream = bus.subscribe().await.unwrap();
+        let dropped = METRICS.bus_dropped.get();
+        for id in 0..5 {
+            bus.publish(BusMessage::for_room(1, b"hi".to_vec(), format!("m-{}", id))).await.unwrap();
+        }
+        // Only the last two are left
+        let delivery = stream.next().await.unwrap();
+        assert_eq!(delivery.message.attributes["message_id"], "m-3");
+        assert!(METRICS.bus_dropped.get() >= dropped + 3);
+    }
+}
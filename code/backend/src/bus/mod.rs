@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use actix::Addr;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use serde::{Serialize, Deserialize};
+
+use crate::api::redis::RedisActor;
+use crate::settings::{BusKind, Settings};
+
+pub mod dead_letter;
+pub mod dedup;
+pub mod memory_bus;
+pub mod pubsub_bus;
+pub mod redis_bus;
+pub mod redis_stream_bus;
+pub mod subscriber;
+
+/// A message travelling over the bus.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BusMessage {
+    pub data: Vec<u8>,
+    pub attributes: HashMap<String, String>,
+    pub ordering_key: String,
+}
+
+impl BusMessage {
+    /// A chat message for `room_id`, ordered within the room.
+    pub fn for_room(room_id: i32, data: Vec<u8>, message_id: String) -> Self {
+        BusMessage {
+            data,
+            // Ordered within a room, rooms publish in parallel (https://cloud.google.com/pubsub/docs/ordering)
+            ordering_key: format!("room-{}", room_id),
+            attributes: HashMap::from([
+                ("room_id".to_string(), room_id.to_string()),
+                ("message_id".to_string(), message_id),
+            ]),
+        }
+    }
+
+    /// A named SSE event for the clients of `room_id`, e.g. `profile_updated`.
+    /// Event names are limited to ASCII letters, digits and `_`.
+    pub fn event_for_room(room_id: i32, event: &str, data: Vec<u8>) -> Self {
+        let mut message = BusMessage::for_room(room_id, data, new_message_id());
+        message.attributes.insert(EVENT_ATTRIBUTE.to_string(), event.to_string());
+        message
+    }
+
+    /// A named SSE event for the personal streams of `user_id`, about
+    /// something in `room_id`. It travels (and is ordered) with the room.
+    pub fn event_for_user(room_id: i32, user_id: i32, event: &str, data: Vec<u8>) -> Self {
+        let mut message = BusMessage::event_for_room(room_id, event, data);
+        message.attributes.insert(USER_ATTRIBUTE.to_string(), user_id.to_string());
+        message
+    }
+}
+
+/// Name of the attribute that turns a room message into a named SSE event.
+pub const EVENT_ATTRIBUTE: &str = "event";
+
+/// Name of the attribute that sends a named event to one user instead of the room.
+pub const USER_ATTRIBUTE: &str = "user_id";
+
+/// Whether `event` can be written on an SSE `event:` line as is.
+pub fn is_valid_event_name(event: &str) -> bool {
+    !event.is_empty() && event.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A server generated id, unique enough to deduplicate deliveries.
+pub fn new_message_id() -> String {
+    format!("{:x}-{:016x}", chrono::Utc::now().timestamp_millis(), rand::random::<u64>())
+}
+
+#[derive(Debug)]
+pub enum BusError {
+    Connect(String),
+    Publish(String),
+    Subscribe(String),
+    Ack(String),
+    /// Reading stored messages back, such as the dead letters.
+    Read(String),
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusError::Connect(e) => write!(f, "failed to connect to message bus: {}", e),
+            BusError::Publish(e) => write!(f, "failed to publish message: {}", e),
+            BusError::Subscribe(e) => write!(f, "failed to subscribe: {}", e),
+            BusError::Ack(e) => write!(f, "failed to ack message: {}", e),
+            BusError::Read(e) => write!(f, "failed to read stored messages: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+/// Backend specific acknowledgement of a received message.
+pub trait Acker: Send {
+    fn ack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>>;
+    fn nack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>>;
+}
+
+/// A received message together with the handle used to acknowledge it.
+pub struct Delivery {
+    pub message: BusMessage,
+    acker: Option<Box<dyn Acker>>,
+}
+
+impl Delivery {
+    pub fn new(message: BusMessage, acker: Option<Box<dyn Acker>>) -> Self {
+        Delivery { message, acker }
+    }
+
+    pub async fn ack(self) -> Result<(), BusError> {
+        match self.acker {
+            Some(acker) => acker.ack().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a nack makes the bus deliver the message again. Buses
+    /// without acks (memory, Redis Pub/Sub) never redeliver.
+    pub fn is_redeliverable(&self) -> bool {
+        self.acker.is_some()
+    }
+
+    pub async fn nack(self) -> Result<(), BusError> {
+        match self.acker {
+            Some(acker) => acker.nack().await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub type DeliveryStream = BoxStream<'static, Delivery>;
+
+/// Transport used to fan chat messages out to every pod.
+pub trait MessageBus: Send + Sync {
+    fn publish(&self, message: BusMessage) -> BoxFuture<'_, Result<(), BusError>>;
+    fn subscribe(&self) -> BoxFuture<'_, Result<DeliveryStream, BusError>>;
+    /// Flushes messages still buffered for publishing, called on shutdown.
+    fn close(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Builds the bus selected by `bus.kind` (`memory`, `redis`, `redis-streams` or `pubsub`).
+pub async fn from_settings(settings: &Settings, redis: Addr<RedisActor>) -> Result<Arc<dyn MessageBus>, BusError> {
+    let bus = &settings.bus;
+    match bus.kind {
+        BusKind::Memory => Ok(Arc::new(memory_bus::MemoryBus::new(2000))),
+        BusKind::Redis => Ok(Arc::new(redis_bus::RedisBus::new(redis, &settings.redis.url, &bus.topic)?)),
+        BusKind::RedisStreams => Ok(Arc::new(redis_stream_bus::RedisStreamBus::new(
+            redis,
+            &settings.redis.url,
+            &bus.stream,
+            &settings.pod_name,
+        ))),
+        BusKind::PubSub => {
+            // Validated by Settings::load
+            let subscription = bus.subscription.as_deref().unwrap_or_default();
+            Ok(Arc::new(pubsub_bus::PubSubBus::new(&bus.topic, subscription).await?))
+        }
+    }
+}
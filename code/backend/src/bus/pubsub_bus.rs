@@ -0,0 +1,96 @@
+use futures::future::BoxFuture;
+use futures_util::StreamExt;
+use google_cloud_googleapis::pubsub::v1::PubsubMessage;
+use google_cloud_pubsub::client::{Client, ClientConfig};
+use google_cloud_pubsub::publisher::Publisher;
+use google_cloud_pubsub::subscriber::ReceivedMessage;
+use google_cloud_pubsub::subscription::Subscription;
+
+use crate::bus::{Acker, BusError, BusMessage, Delivery, DeliveryStream, MessageBus};
+
+/// Google Cloud Pub/Sub backed bus.
+/// Credentials are taken from `GOOGLE_APPLICATION_CREDENTIALS`.
+pub struct PubSubBus {
+    publisher: Publisher,
+    subscription: Subscription,
+}
+
+impl PubSubBus {
+    pub async fn new(topic_id: &str, subscribe_id: &str) -> Result<Self, BusError> {
+        let config = ClientConfig::default()
+            .with_auth()
+            .await
+            .map_err(|e| BusError::Connect(e.to_string()))?;
+        let client = Client::new(config)
+            .await
+            .map_err(|e| BusError::Connect(e.to_string()))?;
+        let publisher = client.topic(topic_id).new_publisher(None);
+        let subscription = client.subscription(subscribe_id);
+
+        Ok(PubSubBus { publisher, subscription })
+    }
+}
+
+struct PubSubAcker(ReceivedMessage);
+
+impl Acker for PubSubAcker {
+    fn ack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>> {
+        Box::pin(async move { self.0.ack().await.map_err(|e| BusError::Ack(e.to_string())) })
+    }
+
+    fn nack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>> {
+        Box::pin(async move { self.0.nack().await.map_err(|e| BusError::Ack(e.to_string())) })
+    }
+}
+
+impl MessageBus for PubSubBus {
+    fn publish(&self, message: BusMessage) -> BoxFuture<'_, Result<(), BusError>> {
+        // https://crates.io/crates/google-cloud-pubsub
+        // https://crates.io/crates/google-cloud-googleapis
+        let msg = PubsubMessage {
+            data: message.data,
+            attributes: message.attributes,
+            // Set ordering_key if needed (https://cloud.google.com/pubsub/docs/ordering)
+            ordering_key: message.ordering_key,
+            ..Default::default()
+        };
+        Box::pin(async move {
+            // There are also `publish_bulk` and `publish_immediately` methods.
+            let awaiter = self.publisher.publish(msg).await;
+            // The get method blocks until a server-generated ID or an error is returned for the published message.
+            awaiter
+                .get()
+                .await
+                .map(|_| ())
+                .map_err(|e| BusError::Publish(e.to_string()))
+        })
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<DeliveryStream, BusError>> {
+        Box::pin(async move {
+            // Pull型ストリーム
+            let stream = self
+                .subscription
+                .subscribe(None)
+                .await
+                .map_err(|e| BusError::Subscribe(e.to_string()))?;
+            let stream = stream
+                .map(|received| {
+                    let message = BusMessage {
+                        data: received.message.data.clone(),
+                        attributes: received.message.attributes.clone(),
+                        ordering_key: received.message.ordering_key.clone(),
+                    };
+                    Delivery::new(message, Some(Box::new(PubSubAcker(received))))
+                })
+                .boxed();
+            Ok(stream)
+        })
+    }
+
+    fn close(&self) -> BoxFuture<'_, ()> {
+        // Clones share the publishing tasks, so this waits for all of them
+        let mut publisher = self.publisher.clone();
+        Box::pin(async move { publisher.shutdown().await })
+    }
+}
@@ -0,0 +1,64 @@
+use actix::Addr;
+use futures::future::BoxFuture;
+use futures_util::StreamExt;
+
+use crate::api::redis::{PublishCommand, RedisActor};
+use crate::library::metrics::METRICS;
+use crate::bus::{BusError, BusMessage, Delivery, DeliveryStream, MessageBus};
+
+/// Redis Pub/Sub backed bus on the existing cluster.
+/// Messages are published through `RedisActor`. Cluster nodes forward
+/// PUBLISH to each other, so subscribing on a single node is enough.
+pub struct RedisBus {
+    redis: Addr<RedisActor>,
+    client: redis::Client,
+    channel: String,
+}
+
+impl RedisBus {
+    pub fn new(redis: Addr<RedisActor>, redis_url: &str, channel: &str) -> Result<Self, BusError> {
+        let client = redis::Client::open(redis_url).map_err(|e| BusError::Connect(e.to_string()))?;
+        Ok(RedisBus { redis, client, channel: channel.to_string() })
+    }
+}
+
+impl MessageBus for RedisBus {
+    fn publish(&self, message: BusMessage) -> BoxFuture<'_, Result<(), BusError>> {
+        Box::pin(async move {
+            let payload = serde_json::to_string(&message).map_err(|e| BusError::Publish(e.to_string()))?;
+            match self.redis.send(PublishCommand { channel: self.channel.clone(), payload }).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(redis_error)) => Err(BusError::Publish(redis_error.to_string())),
+                Err(mailbox_error) => Err(BusError::Publish(mailbox_error.to_string())),
+            }
+        })
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<DeliveryStream, BusError>> {
+        Box::pin(async move {
+            let mut pubsub = self
+                .client
+                .get_async_pubsub()
+                .await
+                .map_err(|e| BusError::Subscribe(e.to_string()))?;
+            pubsub
+                .subscribe(&self.channel)
+                .await
+                .map_err(|e| BusError::Subscribe(e.to_string()))?;
+            let stream = pubsub
+                .into_on_message()
+                .filter_map(|msg| async move {
+                    match serde_json::from_slice::<BusMessage>(msg.get_payload_bytes()) {
+                        Ok(message) => Some(Delivery::new(message, None)),
+                        Err(e) => {
+                            tracing::error!(error = %e, "Invalid message on redis bus");
+                            METRICS.bus_dropped.inc();
+                            None
+                        }
+                    }
+                })
+                .boxed();
+            Ok(stream)
+        })
+    }
+}
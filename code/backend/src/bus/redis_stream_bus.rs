@@ -0,0 +1,362 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use actix::Addr;
+use futures::future::BoxFuture;
+use futures_util::StreamExt;
+use redis::cluster::{ClusterClient, ClusterConfig};
+use redis::cluster_async::ClusterConnection;
+use redis::streams::{StreamAutoClaimReply, StreamId, StreamReadReply};
+use tokio::time::Instant;
+
+use crate::api::redis::{RedisActor, SAddCommand, XAckCommand, XAddCommand};
+use crate::bus::{Acker, BusError, BusMessage, Delivery, DeliveryStream, MessageBus};
+use crate::library::metrics::METRICS;
+use crate::settings::RedisStreamSettings;
+
+/// Room used for messages published without a `room_id` attribute.
+const DEFAULT_ROOM: &str = "0";
+/// Field of the stream entry holding the serialized `BusMessage`.
+const MESSAGE_FIELD: &str = "message";
+/// Entries claimed per stream and XAUTOCLAIM call.
+const CLAIM_COUNT: usize = 100;
+
+/// Redis Streams backed bus with one stream per room.
+///
+/// A pod reads all room streams as its own consumer (the pod name) of the
+/// configured consumer group. The group outlives the pod, so a restarted pod
+/// resumes from the last acked entry, and entries a gone consumer left
+/// pending are claimed with XAUTOCLAIM once idle. Each group sees every
+/// message, so pods that must all see every message (fan-out) each need a
+/// group of their own. All keys share the hash tag in `prefix` (e.g. `{chat}`)
+/// so a single XREADGROUP can cover every room on the cluster.
+pub struct RedisStreamBus {
+    redis: Addr<RedisActor>,
+    redis_urls: Vec<String>,
+    prefix: String,
+    group: String,
+    consumer: String,
+    maxlen: usize,
+    start_id: String,
+    block_ms: usize,
+    claim_idle_ms: usize,
+}
+
+impl RedisStreamBus {
+    pub fn new(redis: Addr<RedisActor>, redis_url: &str, settings: &RedisStreamSettings, consumer: &str) -> Self {
+        RedisStreamBus {
+            redis,
+            redis_urls: vec![redis_url.to_string()],
+            prefix: settings.prefix.clone(),
+            // Validated by Settings::load
+            group: settings.group.clone().unwrap_or_default(),
+            consumer: consumer.to_string(),
+            maxlen: settings.maxlen,
+            start_id: settings.start_id.clone(),
+            block_ms: settings.block_ms,
+            claim_idle_ms: settings.claim_idle_ms,
+        }
+    }
+
+    fn rooms_key(&self) -> String {
+        format!("{}:rooms", self.prefix)
+    }
+
+    fn stream_key(&self, room_id: &str) -> String {
+        format!("{}:room:{}", self.prefix, room_id)
+    }
+}
+
+struct StreamAcker {
+    redis: Addr<RedisActor>,
+    key: String,
+    group: String,
+    id: String,
+}
+
+impl Acker for StreamAcker {
+    fn ack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>> {
+        Box::pin(async move {
+            match self.redis.send(XAckCommand { key: self.key, group: self.group, id: self.id }).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(redis_error)) => Err(BusError::Ack(redis_error.to_string())),
+                Err(mailbox_error) => Err(BusError::Ack(mailbox_error.to_string())),
+            }
+        })
+    }
+
+    fn nack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>> {
+        // Not acking leaves the entry pending. `>` reads never return it again:
+        // it comes back through XAUTOCLAIM once idle for `claim_idle_ms`, or
+        // on a restart, when this consumer re-reads its pending entries.
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A room stream and where this consumer is in it.
+struct RoomStream {
+    key: String,
+    // Starts at "0" to re-read entries that were delivered to this consumer
+    // but never acked, then switches to ">".
+    cursor: String,
+    // Where the next XAUTOCLAIM starts, "0-0" once a pass is complete.
+    claim_cursor: String,
+}
+
+/// State of one subscription: a dedicated connection for blocking reads and
+/// the cursors of every known room stream.
+struct StreamReader {
+    conn: ClusterConnection,
+    redis: Addr<RedisActor>,
+    rooms_key: String,
+    group: String,
+    consumer: String,
+    start_id: String,
+    block_ms: usize,
+    claim_idle_ms: usize,
+    last_claim: Option<Instant>,
+    initialized: bool,
+    streams: Vec<RoomStream>,
+    buffer: VecDeque<Delivery>,
+}
+
+/// XAUTOCLAIM of the entries of `key` pending for longer than `min_idle_ms`
+/// with any consumer of `group`, starting at `cursor`.
+fn autoclaim(key: &str, group: &str, consumer: &str, min_idle_ms: usize, cursor: &str) -> redis::Cmd {
+    let mut cmd = redis::cmd("XAUTOCLAIM");
+    cmd.arg(key).arg(group).arg(consumer).arg(min_idle_ms).arg(cursor).arg("COUNT").arg(CLAIM_COUNT);
+    cmd
+}
+
+/// The `BusMessage` in a stream entry, `None` if it has none or it is invalid.
+fn message_of(entry: &StreamId) -> Option<BusMessage> {
+    let payload: String = entry.get(MESSAGE_FIELD)?;
+    serde_json::from_str(&payload).ok()
+}
+
+impl StreamReader {
+    async fn next(&mut self) -> Delivery {
+        loop {
+            if let Some(delivery) = self.buffer.pop_front() {
+                return delivery;
+            }
+            if let Err(e) = self.read().await {
+                tracing::error!(error = %e, "Failed to read redis streams");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+
+    async fn refresh_streams(&mut self) -> Result<(), redis::RedisError> {
+        let keys: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(&self.rooms_key)
+            .query_async(&mut self.conn)
+            .await?;
+        // Rooms created after we started are read from their first entry,
+        // otherwise anything published before we noticed them would be lost.
+        let start_id = if self.initialized { "0".to_string() } else { self.start_id.clone() };
+        self.initialized = true;
+        for key in keys {
+            if self.streams.iter().any(|stream| stream.key == key) {
+                continue;
+            }
+            let created: Result<(), redis::RedisError> = redis::cmd("XGROUP")
+                .arg("CREATE")
+                .arg(&key)
+                .arg(&self.group)
+                .arg(&start_id)
+                .arg("MKSTREAM")
+                .query_async(&mut self.conn)
+                .await;
+            match created {
+                Ok(_) => {}
+                // The group survives restarts, that is what lets us resume.
+                Err(e) if e.code() == Some("BUSYGROUP") => {}
+                Err(e) => return Err(e),
+            }
+            self.streams.push(RoomStream { key, cursor: "0".to_string(), claim_cursor: "0-0".to_string() });
+        }
+        Ok(())
+    }
+
+    /// Takes over the entries other consumers of the group (e.g. this pod
+    /// before a restart under another name) received but never acked.
+    async fn claim(&mut self) -> Result<(), redis::RedisError> {
+        for i in 0..self.streams.len() {
+            let stream = &self.streams[i];
+            let key = stream.key.clone();
+            let reply: StreamAutoClaimReply =
+                autoclaim(&key, &self.group, &self.consumer, self.claim_idle_ms, &stream.claim_cursor)
+                    .query_async(&mut self.conn)
+                    .await?;
+            if !reply.claimed.is_empty() {
+                tracing::warn!(stream = %key, claimed = reply.claimed.len(), "Claimed pending stream entries");
+            }
+            self.streams[i].claim_cursor = reply.next_stream_id;
+            self.push_entries(&key, reply.claimed).await;
+        }
+        Ok(())
+    }
+
+    async fn push_entries(&mut self, key: &str, entries: Vec<StreamId>) {
+        for entry in entries {
+            let acker = StreamAcker {
+                redis: self.redis.clone(),
+                key: key.to_string(),
+                group: self.group.clone(),
+                id: entry.id.clone(),
+            };
+            match message_of(&entry) {
+                Some(message) => self.buffer.push_back(Delivery::new(message, Some(Box::new(acker)))),
+                None => {
+                    tracing::error!(entry = %entry.id, stream = %key, "Invalid stream entry");
+                    METRICS.bus_dropped.inc();
+                    // Ack it anyway, it would otherwise be re-read forever.
+                    let _ = Delivery::new(BusMessage::default(), Some(Box::new(acker))).ack().await;
+                }
+            }
+        }
+    }
+
+    async fn read(&mut self) -> Result<(), redis::RedisError> {
+        self.refresh_streams().await?;
+        if self.streams.is_empty() {
+            tokio::time::sleep(Duration::from_millis(self.block_ms as u64)).await;
+            return Ok(());
+        }
+        let claim_interval = Duration::from_millis(self.claim_idle_ms as u64);
+        if self.last_claim.is_none_or(|at| at.elapsed() >= claim_interval) {
+            self.last_claim = Some(Instant::now());
+            self.claim().await?;
+            if !self.buffer.is_empty() {
+                return Ok(());
+            }
+        }
+
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP")
+            .arg(&self.group)
+            .arg(&self.consumer)
+            .arg("COUNT")
+            .arg(100)
+            .arg("BLOCK")
+            .arg(self.block_ms)
+            .arg("STREAMS");
+        for stream in &self.streams {
+            cmd.arg(&stream.key);
+        }
+        for stream in &self.streams {
+            cmd.arg(&stream.cursor);
+        }
+        let reply: Option<StreamReadReply> = cmd.query_async(&mut self.conn).await?;
+        let Some(reply) = reply else {
+            // BLOCK timed out
+            return Ok(());
+        };
+
+        for stream in reply.keys {
+            if let Some(room) = self.streams.iter_mut().find(|room| room.key == stream.key) {
+                if room.cursor != ">" {
+                    room.cursor = match stream.ids.last() {
+                        Some(last) => last.id.clone(),
+                        None => ">".to_string(),
+                    };
+                }
+            }
+            self.push_entries(&stream.key, stream.ids).await;
+        }
+        Ok(())
+    }
+}
+
+impl MessageBus for RedisStreamBus {
+    fn publish(&self, message: BusMessage) -> BoxFuture<'_, Result<(), BusError>> {
+        Box::pin(async move {
+            let room_id = message.attributes.get("room_id").map(|r| r.as_str()).unwrap_or(DEFAULT_ROOM);
+            let key = self.stream_key(room_id);
+            let value = serde_json::to_string(&message).map_err(|e| BusError::Publish(e.to_string()))?;
+
+            match self.redis.send(SAddCommand { key: self.rooms_key(), member: key.clone() }).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(redis_error)) => return Err(BusError::Publish(redis_error.to_string())),
+                Err(mailbox_error) => return Err(BusError::Publish(mailbox_error.to_string())),
+            }
+            let command = XAddCommand { key, maxlen: self.maxlen, field: MESSAGE_FIELD.to_string(), value };
+            match self.redis.send(command).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(redis_error)) => Err(BusError::Publish(redis_error.to_string())),
+                Err(mailbox_error) => Err(BusError::Publish(mailbox_error.to_string())),
+            }
+        })
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<DeliveryStream, BusError>> {
+        Box::pin(async move {
+            // XREADGROUP BLOCK holds the connection, so it gets its own one
+            // with a response timeout longer than the block time.
+            let client = ClusterClient::new(self.redis_urls.clone())
+                .map_err(|e| BusError::Connect(e.to_string()))?;
+            let config = ClusterConfig::new()
+                .set_connection_timeout(Duration::from_secs(1))
+                .set_response_timeout(Duration::from_millis(self.block_ms as u64) + Duration::from_secs(5));
+            let conn = client
+                .get_async_connection_with_config(config)
+                .await
+                .map_err(|e| BusError::Subscribe(e.to_string()))?;
+
+            let reader = StreamReader {
+                conn,
+                redis: self.redis.clone(),
+                rooms_key: self.rooms_key(),
+                group: self.group.clone(),
+                consumer: self.consumer.clone(),
+                start_id: self.start_id.clone(),
+                block_ms: self.block_ms,
+                claim_idle_ms: self.claim_idle_ms,
+                last_claim: None,
+                initialized: false,
+                streams: Vec::new(),
+                buffer: VecDeque::new(),
+            };
+            let stream = futures::stream::unfold(reader, |mut reader| async move {
+                let delivery = reader.next().await;
+                Some((delivery, reader))
+            })
+            .boxed();
+            Ok(stream)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Value;
+
+    #[test]
+    fn claims_for_this_consumer_of_the_shared_group() {
+        let cmd = autoclaim("{chat}:room:1", "chat", "chat-7f9c-x2", 60000, "0-0");
+        let args: Vec<String> = cmd
+            .args_iter()
+            .map(|arg| match arg {
+                redis::Arg::Simple(bytes) => String::from_utf8(bytes.to_vec()).unwrap(),
+                redis::Arg::Cursor => "<cursor>".to_string(),
+            })
+            .collect();
+        assert_eq!(args, ["XAUTOCLAIM", "{chat}:room:1", "chat", "chat-7f9c-x2", "60000", "0-0", "COUNT", "100"]);
+    }
+
+    #[test]
+    fn entries_without_a_valid_message_are_rejected() {
+        let message = BusMessage::for_room(1, b"hello".to_vec(), "m-1".to_string());
+        let entry = |field: &str, value: String| StreamId {
+            id: "1-0".to_string(),
+            map: [(field.to_string(), Value::BulkString(value.into_bytes()))].into(),
+        };
+        let decoded = message_of(&entry(MESSAGE_FIELD, serde_json::to_string(&message).unwrap())).unwrap();
+        assert_eq!(decoded.data, message.data);
+        assert_eq!(decoded.attributes, message.attributes);
+        assert!(message_of(&entry(MESSAGE_FIELD, "{not json".to_string())).is_none());
+        assert!(message_of(&entry("other", serde_json::to_string(&message).unwrap())).is_none());
+    }
+}
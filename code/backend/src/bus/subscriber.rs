@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::time::sleep;
+use tokio_stream::StreamExt;
+use tracing::Instrument;
+
+use crate::api::broadcaster::Broadcaster;
+use crate::bus::dead_letter::DeadLetterStore;
+use crate::bus::dedup::DedupStore;
+use crate::bus::{is_valid_event_name, BusMessage, Delivery, MessageBus, EVENT_ATTRIBUTE, USER_ATTRIBUTE};
+use crate::library::shutdown::Shutdown;
+use crate::library::metrics::METRICS;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Failing messages whose attempts are counted. Ids of messages that never
+/// come back would otherwise pile up.
+const MAX_TRACKED_FAILURES: usize = 10_000;
+
+/// Delay before the next subscribe attempt: doubled, up to `MAX_BACKOFF`.
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+/// Where a valid message goes.
+enum Route {
+    Room { room_id: i32, event: Option<String> },
+    User { user_id: i32, event: String },
+}
+
+/// The route and text of `message`, or why it cannot be delivered.
+fn route(message: &BusMessage) -> Result<(Route, String), String> {
+    // Route to the room the publisher tagged the message with
+    let room_id = message
+        .attributes
+        .get("room_id")
+        .and_then(|r| r.parse::<i32>().ok())
+        .ok_or("missing or invalid room_id attribute")?;
+    let data = String::from_utf8(message.data.clone()).map_err(|e| format!("payload is not valid UTF-8: {}", e))?;
+    // A line break in the name would let the payload forge SSE fields
+    let event = message.attributes.get(EVENT_ATTRIBUTE).cloned();
+    if let Some(event) = event.as_deref().filter(|event| !is_valid_event_name(event)) {
+        return Err(format!("invalid event attribute {:?}", event));
+    }
+    // Personal events (mentions) go to the user's own streams, not to the room
+    let user_id = message.attributes.get(USER_ATTRIBUTE).map(|user_id| user_id.parse::<i32>());
+    let route = match (user_id, event) {
+        (None, event) => Route::Room { room_id, event },
+        (Some(Ok(user_id)), Some(event)) => Route::User { user_id, event },
+        (Some(_), _) => return Err("invalid user_id attribute, or no event for it".to_string()),
+    };
+    Ok((route, data))
+}
+
+/// State of the subscriber loop, shared with the health endpoints.
+#[derive(Default)]
+pub struct SubscriberHealth {
+    connected: AtomicBool,
+    consumed: AtomicU64,
+    duplicates: AtomicU64,
+    dead_lettered: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberStatus {
+    pub connected: bool,
+    pub consumed: u64,
+    pub duplicates: u64,
+    pub dead_lettered: u64,
+    pub last_error: Option<String>,
+}
+
+impl SubscriberHealth {
+    /// Whether messages are currently being consumed.
+    pub fn is_healthy(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn status(&self) -> SubscriberStatus {
+        SubscriberStatus {
+            connected: self.connected.load(Ordering::SeqCst),
+            consumed: self.consumed.load(Ordering::SeqCst),
+            duplicates: self.duplicates.load(Ordering::SeqCst),
+            dead_lettered: self.dead_lettered.load(Ordering::SeqCst),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    pub(crate) fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::SeqCst);
+    }
+
+    pub(crate) fn set_error(&self, error: String) {
+        tracing::error!(error = %error, "Subscriber error");
+        *self.last_error.lock().unwrap() = Some(error);
+    }
+}
+
+/// Consumes the message bus and forwards messages to the SSE broadcaster.
+/// Reconnects with exponential backoff whenever subscribing fails or the
+/// stream ends, so a bus outage never stops consumption for good.
+pub struct Subscriber {
+    bus: Arc<dyn MessageBus>,
+    broadcaster: Arc<Broadcaster>,
+    dead_letter: Arc<dyn DeadLetterStore>,
+    dedup: Arc<dyn DedupStore>,
+    health: Arc<SubscriberHealth>,
+    /// Deliveries a message may fail before it is dead-lettered.
+    max_delivery_attempts: u32,
+    /// Failed deliveries so far, by message_id.
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+impl Subscriber {
+    pub fn new(
+        bus: Arc<dyn MessageBus>,
+        broadcaster: Arc<Broadcaster>,
+        dead_letter: Arc<dyn DeadLetterStore>,
+        dedup: Arc<dyn DedupStore>,
+        health: Arc<SubscriberHealth>,
+        max_delivery_attempts: u32,
+    ) -> Self {
+        Subscriber {
+            bus,
+            broadcaster,
+            dead_letter,
+            dedup,
+            health,
+            max_delivery_attempts,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Consumes until `shutdown` begins. The message in hand is still
+    /// handled and acked, then the stream is dropped so nothing new is pulled.
+    pub async fn run(self, shutdown: Arc<Shutdown>) {
+        let mut backoff = MIN_BACKOFF;
+        while !shutdown.is_draining() {
+            match self.bus.subscribe().await {
+                Ok(mut stream) => {
+                    self.health.set_connected(true);
+                    tracing::info!("Subscribed to message bus");
+                    loop {
+                        let delivery = tokio::select! {
+                            biased;
+                            _ = shutdown.wait() => break,
+                            delivery = stream.next() => delivery,
+                        };
+                        let Some(delivery) = delivery else {
+                            self.health.set_error("Message bus stream ended".to_string());
+                            break;
+                        };
+                        backoff = MIN_BACKOFF;
+                        // Same request_id as the POST that published it, so one grep finds both ends
+                        let attributes = &delivery.message.attributes;
+                        let span = tracing::info_span!(
+                            "delivery",
+                            request_id = attributes.get("request_id").map(String::as_str),
+                            message_id = attributes.get("message_id").map(String::as_str),
+                            room_id = attributes.get("room_id").map(String::as_str),
+                        );
+                        self.handle(delivery).instrument(span).await;
+                    }
+                    self.health.set_connected(false);
+                }
+                Err(e) => {
+                    self.health.set_connected(false);
+                    self.health.set_error(e.to_string());
+                }
+            }
+            if shutdown.is_draining() {
+                break;
+            }
+            tracing::warn!(backoff_secs = backoff.as_secs(), "Resubscribing to message bus");
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = shutdown.wait() => break,
+            }
+            backoff = next_backoff(backoff);
+        }
+        tracing::info!("Subscriber stopped");
+    }
+
+    async fn handle(&self, delivery: Delivery) {
+        self.health.consumed.fetch_add(1, Ordering::SeqCst);
+        METRICS.bus_consumed.inc();
+        let (route, data) = match route(&delivery.message) {
+            Ok(routed) => routed,
+            Err(reason) => {
+                self.failed(delivery, &reason).await;
+                return;
+            }
+        };
+        // Redeliveries and retried POSTs carry the same message_id. Checked
+        // after the message is known to be valid, so one nacked above is
+        // not taken for a duplicate when it comes back.
+        if let Some(message_id) = delivery.message.attributes.get("message_id") {
+            if !self.dedup.first_delivery(message_id).await {
+                self.health.duplicates.fetch_add(1, Ordering::SeqCst);
+                METRICS.bus_duplicates.inc();
+                if let Err(e) = delivery.ack().await {
+                    METRICS.bus_ack_failures.inc();
+                    self.health.set_error(e.to_string());
+                }
+                return;
+            }
+        }
+        match route {
+            Route::Room { room_id, event } => self.broadcaster.send(room_id, event, data),
+            Route::User { user_id, event } => self.broadcaster.send_to_user(user_id, event, data),
+        }
+        if let Err(e) = delivery.ack().await {
+            METRICS.bus_ack_failures.inc();
+            self.health.set_error(e.to_string());
+            // If ack fails, wait for a while before retrying
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Nacks a message that could not be handled, so it is delivered again,
+    /// until it failed `max_delivery_attempts` times and is dead-lettered.
+    /// Messages that are never redelivered, or without a message_id to
+    /// count by, are dead-lettered at once.
+    async fn failed(&self, delivery: Delivery, reason: &str) {
+        let message_id = delivery.message.attributes.get("message_id").filter(|_| delivery.is_redeliverable());
+        let attempts = match message_id {
+            Some(message_id) => self.count_failure(message_id),
+            None => self.max_delivery_attempts,
+        };
+        if attempts < self.max_delivery_attempts {
+            tracing::warn!(reason, attempts, "Nacking message");
+            if let Err(e) = delivery.nack().await {
+                METRICS.bus_ack_failures.inc();
+                self.health.set_error(e.to_string());
+            }
+            return;
+        }
+        if let Some(message_id) = message_id {
+            self.failures.lock().unwrap().remove(message_id);
+        }
+        self.dead_letter(delivery, reason).await;
+    }
+
+    /// Records one more failed delivery of `message_id`, returns how many it had.
+    fn count_failure(&self, message_id: &str) -> u32 {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED_FAILURES && !failures.contains_key(message_id) {
+            failures.clear();
+        }
+        let count = failures.entry(message_id.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Stores the message in the dead-letter sink and acks it. If it could
+    /// not be stored it is nacked instead, so it is redelivered, not lost.
+    async fn dead_letter(&self, delivery: Delivery, reason: &str) {
+        tracing::error!(reason, "Dead-lettering message");
+        self.health.dead_lettered.fetch_add(1, Ordering::SeqCst);
+        METRICS.bus_dead_lettered.inc();
+        let result = match self.dead_letter.store(&delivery.message, reason).await {
+            Ok(_) => delivery.ack().await,
+            Err(e) => {
+                self.health.set_error(e.to_string());
+                delivery.nack().await
+            }
+        };
+        if let Err(e) = result {
+            METRICS.bus_ack_failures.inc();
+            self.health.set_error(e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
+
+    use futures::future::BoxFuture;
+    use tokio::sync::watch;
+    use tokio::time::Instant;
+
+    use crate::bus::memory_bus::MemoryBus;
+    use crate::bus::{Acker, BusError, DeliveryStream};
+    use crate::settings::SseSettings;
+
+    /// The memory bus, failing its first `failures` subscribes. Sending on
+    /// `end` ends the streams handed out so far.
+    struct FlakyBus {
+        inner: MemoryBus,
+        failures: AtomicUsize,
+        subscribed: Mutex<Vec<Instant>>,
+        end: watch::Sender<()>,
+    }
+
+    impl FlakyBus {
+        fn new(failures: usize) -> Self {
+            FlakyBus {
+                inner: MemoryBus::new(16),
+                failures: AtomicUsize::new(failures),
+                subscribed: Mutex::new(Vec::new()),
+                end: watch::channel(()).0,
+            }
+        }
+
+        /// Seconds between consecutive subscribe attempts.
+        fn gaps(&self) -> Vec<u64> {
+            let subscribed = self.subscribed.lock().unwrap();
+            subscribed.windows(2).map(|w| (w[1] - w[0]).as_secs()).collect()
+        }
+    }
+
+    impl MessageBus for FlakyBus {
+        fn publish(&self, message: BusMessage) -> BoxFuture<'_, Result<(), BusError>> {
+            self.inner.publish(message)
+        }
+
+        fn subscribe(&self) -> BoxFuture<'_, Result<DeliveryStream, BusError>> {
+            Box::pin(async move {
+                self.subscribed.lock().unwrap().push(Instant::now());
+                if self.failures.load(Ordering::SeqCst) > 0 {
+                    self.failures.fetch_sub(1, Ordering::SeqCst);
+                    return Err(BusError::Subscribe("bus unavailable".to_string()));
+                }
+                let mut end = self.end.subscribe();
+                let stream = self.inner.subscribe().await?;
+                let ended = async move {
+                    let _ = end.changed().await;
+                };
+                Ok(futures_util::StreamExt::boxed(futures_util::StreamExt::take_until(stream, ended)))
+            })
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryDeadLetters(Mutex<Vec<String>>);
+
+    impl DeadLetterStore for MemoryDeadLetters {
+        fn store<'a>(&'a self, _message: &'a BusMessage, reason: &'a str) -> BoxFuture<'a, Result<(), BusError>> {
+            self.0.lock().unwrap().push(reason.to_string());
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryDedup(Mutex<HashSet<String>>);
+
+    impl DedupStore for MemoryDedup {
+        fn first_delivery<'a>(&'a self, message_id: &'a str) -> BoxFuture<'a, bool> {
+            let first = self.0.lock().unwrap().insert(message_id.to_string());
+            Box::pin(async move { first })
+        }
+    }
+
+    /// Records how each delivery was settled.
+    struct RecordingAcker(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Acker for RecordingAcker {
+        fn ack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>> {
+            self.0.lock().unwrap().push("ack");
+            Box::pin(async { Ok(()) })
+        }
+
+        fn nack(self: Box<Self>) -> BoxFuture<'static, Result<(), BusError>> {
+            self.0.lock().unwrap().push("nack");
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn broadcaster() -> Arc<Broadcaster> {
+        Arc::new(Broadcaster::new(SseSettings {
+            capacity: 16,
+            history_size: 16,
+            heartbeat: Duration::from_secs(60),
+            max_connections_per_user: 0,
+            retry_ms: 1000,
+            room_idle_ttl: Duration::from_secs(60),
+        }))
+    }
+
+    fn subscriber(
+        bus: Arc<dyn MessageBus>,
+        dead_letters: Arc<MemoryDeadLetters>,
+        health: Arc<SubscriberHealth>,
+    ) -> Subscriber {
+        Subscriber::new(bus, broadcaster(), dead_letters, Arc::new(MemoryDedup::default()), health, 3)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_up_to_a_minute() {
+        let bus = Arc::new(FlakyBus::new(usize::MAX));
+        let health = Arc::new(SubscriberHealth::default());
+        let shutdown = Arc::new(Shutdown::default());
+        let task = tokio::spawn(subscriber(bus.clone(), Default::default(), health).run(shutdown.clone()));
+
+        sleep(Duration::from_secs(300)).await;
+        shutdown.begin();
+        task.await.unwrap();
+        assert_eq!(bus.gaps()[..8], [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn health_follows_the_subscription() {
+        let bus = Arc::new(FlakyBus::new(1));
+        let health = Arc::new(SubscriberHealth::default());
+        let shutdown = Arc::new(Shutdown::default());
+        let task = tokio::spawn(subscriber(bus.clone(), Default::default(), health.clone()).run(shutdown.clone()));
+
+        // Subscribing failed, retried after a second
+        sleep(Duration::from_millis(500)).await;
+        assert!(!health.is_healthy());
+        assert!(health.status().last_error.unwrap().contains("bus unavailable"));
+        sleep(Duration::from_secs(1)).await;
+        assert!(health.is_healthy());
+
+        bus.end.send_replace(());
+        sleep(Duration::from_millis(100)).await;
+        assert!(!health.is_healthy());
+        assert_eq!(health.status().last_error.as_deref(), Some("Message bus stream ended"));
+
+        // Backoff doubled by the first failure
+        sleep(Duration::from_secs(2)).await;
+        assert!(health.is_healthy());
+        assert_eq!(bus.gaps(), [1, 2]);
+
+        shutdown.begin();
+        task.await.unwrap();
+        assert!(!health.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn failing_messages_are_nacked_then_dead_lettered() {
+        let dead_letters = Arc::new(MemoryDeadLetters::default());
+        let health = Arc::new(SubscriberHealth::default());
+        let subscriber = subscriber(Arc::new(MemoryBus::new(16)), dead_letters.clone(), health.clone());
+        let settled = Arc::new(Mutex::new(Vec::new()));
+        let message = BusMessage::for_room(1, vec![0xff, 0xfe], "m-1".to_string());
+
+        for _ in 0..3 {
+            let acker = RecordingAcker(settled.clone());
+            subscriber.handle(Delivery::new(message.clone(), Some(Box::new(acker)))).await;
+        }
+        assert_eq!(*settled.lock().unwrap(), ["nack", "nack", "ack"]);
+        let reasons = dead_letters.0.lock().unwrap().clone();
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].starts_with("payload is not valid UTF-8"), "{}", reasons[0]);
+        assert_eq!(health.status().dead_lettered, 1);
+        assert!(subscriber.failures.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_message_id_delivered_twice_is_forwarded_once() {
+        let broadcaster = broadcaster();
+        let health = Arc::new(SubscriberHealth::default());
+        let subscriber = Subscriber::new(
+            Arc::new(MemoryBus::new(16)),
+            broadcaster.clone(),
+            Arc::new(MemoryDeadLetters::default()),
+            Arc::new(MemoryDedup::default()),
+            health.clone(),
+            3,
+        );
+        let mut stream = Box::pin(broadcaster.clone().subscribe(1, "alice").unwrap());
+        assert_eq!(stream.next().await.unwrap(), "retry: 1000\n\n");
+
+        let settled = Arc::new(Mutex::new(Vec::new()));
+        for data in ["hello", "hello again"] {
+            let message = BusMessage::for_room(1, data.as_bytes().to_vec(), "m-1".to_string());
+            let acker = RecordingAcker(settled.clone());
+            subscriber.handle(Delivery::new(message, Some(Box::new(acker)))).await;
+        }
+        let other = BusMessage::for_room(1, b"bye".to_vec(), "m-2".to_string());
+        subscriber.handle(Delivery::new(other, None)).await;
+
+        // Both copies are acked, only the first reaches the client
+        assert_eq!(*settled.lock().unwrap(), ["ack", "ack"]);
+        assert_eq!(health.status().duplicates, 1);
+        assert!(String::from_utf8_lossy(&stream.next().await.unwrap()).contains("data: hello\n"));
+        assert!(String::from_utf8_lossy(&stream.next().await.unwrap()).contains("data: bye\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn messages_the_memory_bus_cannot_redeliver_are_dead_lettered_at_once() {
+        let bus = Arc::new(MemoryBus::new(16));
+        let dead_letters = Arc::new(MemoryDeadLetters::default());
+        let health = Arc::new(SubscriberHealth::default());
+        let shutdown = Arc::new(Shutdown::default());
+        let task = tokio::spawn(subscriber(bus.clone(), dead_letters.clone(), health.clone()).run(shutdown.clone()));
+
+        sleep(Duration::from_millis(10)).await;
+        let mut message = BusMessage::for_room(1, b"hello".to_vec(), "m-1".to_string());
+        message.attributes.remove("room_id");
+        bus.publish(message).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(*dead_letters.0.lock().unwrap(), ["missing or invalid room_id attribute"]);
+        assert_eq!(health.status().dead_lettered, 1);
+        assert!(health.is_healthy());
+        shutdown.begin();
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,12 @@
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::PgPool;
+
+/// The files in `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Applies pending migrations and returns the version the schema is now at.
+/// Fails if an applied migration was edited afterwards.
+pub async fn run(pool: &PgPool) -> Result<i64, MigrateError> {
+    MIGRATOR.run(pool).await?;
+    Ok(MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0))
+}
@@ -1,3 +1,5 @@
+pub mod migrate;
 pub mod pool;
 pub mod model;
-pub mod repository;
\ No newline at end of file
+pub mod repository;
+pub mod seed;
\ No newline at end of file
@@ -0,0 +1,34 @@
+use serde::{Serialize, Deserialize};
+
+/// A file uploaded to a room.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    pub id: i64,
+    pub room_id: i32,
+    pub uploader_id: Option<i32>,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    #[serde(skip)]
+    pub storage_key: String,
+    #[serde(skip)]
+    pub thumbnail_key: Option<String>,
+    /// Set for images.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// An attachment about to be stored.
+#[derive(Debug)]
+pub struct NewAttachment<'a> {
+    pub room_id: i32,
+    pub uploader_id: i32,
+    pub file_name: &'a str,
+    pub content_type: &'a str,
+    pub size_bytes: i64,
+    pub storage_key: &'a str,
+    pub thumbnail_key: Option<&'a str>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
@@ -0,0 +1,29 @@
+use serde::{Serialize, Deserialize};
+
+/// A row of the append-only `audit_events` table.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Who caused an audited event, and from where.
+#[derive(Debug, Clone, Default)]
+pub struct Actor {
+    pub name: String,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl Actor {
+    /// Changes made outside of a request, e.g. by `chat-admin`.
+    pub fn system(name: &str) -> Self {
+        Actor { name: name.to_string(), ..Default::default() }
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Serialize, Deserialize};
+
+/// A user mentioned in a room message, an entry of their notification inbox.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mention {
+    pub id: i64,
+    /// The mentioned user.
+    pub user_id: i32,
+    pub room_id: i32,
+    pub message_id: String,
+    pub sender_id: Option<i32>,
+    /// `None` once the sender is deleted.
+    pub sender_name: Option<String>,
+    /// The start of the message.
+    pub excerpt: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// `None` while unread.
+    pub read_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Mentions of one message about to be stored.
+#[derive(Debug)]
+pub struct NewMentions<'a> {
+    pub room_id: i32,
+    pub message_id: &'a str,
+    pub sender_id: i32,
+    pub excerpt: &'a str,
+    pub user_ids: &'a [i32],
+}
@@ -2,3 +2,6 @@ pub mod user;
 pub mod service;
 pub mod room;
 pub mod room_user;
+pub mod audit_event;
+pub mod attachment;
+pub mod mention;
@@ -3,6 +3,7 @@ use serde::{Serialize, Deserialize};
 use crate::db::model::user::User;
 use crate::db::model::room::Room;
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RoomUser {
     pub room: Room,
@@ -1,5 +1,6 @@
 use serde::{Serialize, Deserialize};
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Service {
     pub id: i32,
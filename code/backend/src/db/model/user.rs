@@ -1,15 +1,77 @@
 use serde::{Serialize, Deserialize};
 
+/// Everything other users may see of a user.
 #[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
 pub struct User {
    pub id: i32,
    pub name: String,
+   pub display_name: Option<String>,
+   pub avatar_url: Option<String>,
+   pub bio: Option<String>,
+   pub status_message: Option<String>,
    pub updated_at: chrono::NaiveDateTime,
    pub created_at: chrono::NaiveDateTime,
 }
 
+impl From<User> for UserData {
+   fn from(user: User) -> Self {
+      UserData { id: user.id, name: user.name }
+   }
+}
+
 #[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
 pub struct UserData {
    pub id: i32,
    pub name: String,
 }
+
+/// A user as other users see them.
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct Profile {
+   pub id: i32,
+   pub name: String,
+   pub display_name: Option<String>,
+   pub avatar_url: Option<String>,
+   pub bio: Option<String>,
+   pub status_message: Option<String>,
+}
+
+/// Fields of a profile update. `None` keeps the current value, an empty
+/// string clears it.
+#[derive(Debug, Default)]
+pub struct ProfileChanges<'a> {
+   pub display_name: Option<&'a str>,
+   pub avatar_url: Option<&'a str>,
+   pub bio: Option<&'a str>,
+   pub status_message: Option<&'a str>,
+}
+
+/// What a user may do. Stored in `users.role` and carried in the JWT.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+   #[default]
+   User,
+   Admin,
+}
+
+impl Role {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         Role::User => "user",
+         Role::Admin => "admin",
+      }
+   }
+}
+
+impl std::str::FromStr for Role {
+   type Err = String;
+
+   fn from_str(s: &str) -> Result<Self, Self::Err> {
+      match s {
+         "user" => Ok(Role::User),
+         "admin" => Ok(Role::Admin),
+         other => Err(format!("unknown role '{}', expected user or admin", other)),
+      }
+   }
+}
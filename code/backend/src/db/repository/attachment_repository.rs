@@ -0,0 +1,53 @@
+use crate::db::model::attachment::{Attachment, NewAttachment};
+use sqlx::{PgPool, Error};
+
+#[derive(Clone)]
+pub struct AttachmentRepository {
+    pool: PgPool,
+}
+
+impl AttachmentRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // 新規作成 (中身はオブジェクトストアに保存済み)
+    pub async fn create(&self, new: &NewAttachment<'_>) -> Result<Attachment, Error> {
+        sqlx::query_as!(
+            Attachment,
+            "INSERT INTO attachments
+                (room_id, uploader_id, file_name, content_type, size_bytes, storage_key, thumbnail_key, width, height)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             RETURNING id, room_id, uploader_id, file_name, content_type, size_bytes, storage_key, thumbnail_key,
+                       width, height, created_at",
+            new.room_id, new.uploader_id, new.file_name, new.content_type, new.size_bytes,
+            new.storage_key, new.thumbnail_key, new.width, new.height
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    // 1件取得
+    pub async fn find(&self, id: i64) -> Result<Option<Attachment>, Error> {
+        sqlx::query_as!(
+            Attachment,
+            "SELECT id, room_id, uploader_id, file_name, content_type, size_bytes, storage_key, thumbnail_key,
+                    width, height, created_at
+             FROM attachments WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // ルームの添付ファイルのキー (サムネイル含む)。ルーム削除時にストアから消すため
+    pub async fn storage_keys_of_room(&self, room_id: i32) -> Result<Vec<String>, Error> {
+        let rows = sqlx::query!(
+            "SELECT storage_key, thumbnail_key FROM attachments WHERE room_id = $1",
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().flat_map(|r| std::iter::once(r.storage_key).chain(r.thumbnail_key)).collect())
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool, Error};
+
+use crate::db::model::audit_event::{Actor, AuditEvent};
+
+#[derive(Clone)]
+pub struct AuditRepository {
+    pool: PgPool,
+}
+
+/// Filters of [`AuditRepository::query`], all optional.
+#[derive(Debug, Default)]
+pub struct AuditFilter<'a> {
+    pub actor: Option<&'a str>,
+    pub action: Option<&'a str>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Only events older than this id, for paging.
+    pub before_id: Option<i64>,
+    pub limit: i64,
+}
+
+// 書き込み。他のリポジトリからトランザクション内で呼べるようにexecutorを受け取る
+pub async fn write<'e, E: PgExecutor<'e>>(
+    executor: E,
+    actor: &Actor,
+    action: &str,
+    target: Option<&str>,
+    details: Value,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO audit_events (actor, action, target, details, ip, request_id) VALUES ($1, $2, $3, $4, $5, $6)",
+        actor.name, action, target, details, actor.ip, actor.request_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+impl AuditRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // 記録。targetは対象 (例: "user:3")
+    pub async fn record(&self, actor: &Actor, action: &str, target: Option<&str>, details: Value) -> Result<(), Error> {
+        write(&self.pool, actor, action, target, details).await
+    }
+
+    // 記録するが、失敗してもリクエストは止めない (ログイン・認証エラー用)
+    pub async fn record_or_log(&self, actor: &Actor, action: &str, target: Option<&str>, details: Value) {
+        if let Err(e) = self.record(actor, action, target, details).await {
+            tracing::error!(error = %e, action, actor = %actor.name, "Failed to write audit event");
+        }
+    }
+
+    // 検索。新しい順
+    pub async fn query(&self, filter: &AuditFilter<'_>) -> Result<Vec<AuditEvent>, Error> {
+        sqlx::query_as!(
+            AuditEvent,
+            "SELECT id, actor, action, target, details, ip, request_id, created_at FROM audit_events
+             WHERE ($1::text IS NULL OR actor = $1)
+               AND ($2::text IS NULL OR action = $2)
+               AND ($3::timestamptz IS NULL OR created_at >= $3)
+               AND ($4::timestamptz IS NULL OR created_at < $4)
+               AND ($5::bigint IS NULL OR id < $5)
+             ORDER BY id DESC LIMIT $6",
+            filter.actor, filter.action, filter.from, filter.to, filter.before_id, filter.limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,91 @@
+use crate::db::model::mention::{Mention, NewMentions};
+use sqlx::{PgPool, Error};
+
+#[derive(Clone)]
+pub struct MentionRepository {
+    pool: PgPool,
+}
+
+impl MentionRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // 新規作成。同じメッセージで既にメンションされているユーザーの分は作らず、作った分だけ返す
+    pub async fn create(&self, new: &NewMentions<'_>) -> Result<Vec<Mention>, Error> {
+        sqlx::query_as!(
+            Mention,
+            r#"WITH inserted AS (
+                 INSERT INTO mentions (user_id, room_id, message_id, sender_id, excerpt)
+                 SELECT user_id, $2, $3, $4, $5 FROM unnest($1::integer[]) AS user_id
+                 ON CONFLICT (user_id, message_id) DO NOTHING
+                 RETURNING id, user_id, room_id, message_id, sender_id, excerpt, created_at, read_at
+             )
+             SELECT i.id AS "id!", i.user_id AS "user_id!", i.room_id AS "room_id!", i.message_id AS "message_id!",
+                    i.sender_id, u.name AS "sender_name?", i.excerpt AS "excerpt!", i.created_at AS "created_at!",
+                    i.read_at
+             FROM inserted i LEFT JOIN users u ON u.id = i.sender_id
+             ORDER BY i.id"#,
+            new.user_ids, new.room_id, new.message_id, new.sender_id, new.excerpt
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // 受信箱。新しい順、before_id より古いものを limit 件
+    pub async fn inbox(&self, user_id: i32, unread_only: bool, before_id: Option<i64>, limit: i64) -> Result<Vec<Mention>, Error> {
+        sqlx::query_as!(
+            Mention,
+            r#"SELECT m.id, m.user_id, m.room_id, m.message_id, m.sender_id, u.name AS "sender_name?", m.excerpt,
+                      m.created_at, m.read_at
+               FROM mentions m LEFT JOIN users u ON u.id = m.sender_id
+               WHERE m.user_id = $1 AND (NOT $2 OR m.read_at IS NULL) AND ($3::bigint IS NULL OR m.id < $3)
+               ORDER BY m.id DESC
+               LIMIT $4"#,
+            user_id, unread_only, before_id, limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // 未読件数
+    pub async fn unread_count(&self, user_id: i32) -> Result<i64, Error> {
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM mentions WHERE user_id = $1 AND read_at IS NULL"#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.count)
+    }
+
+    // 既読・未読にする。既読の日時は最初に読んだ時のまま。他のユーザーのものは None
+    pub async fn set_read(&self, user_id: i32, id: i64, read: bool) -> Result<Option<Mention>, Error> {
+        sqlx::query_as!(
+            Mention,
+            r#"WITH updated AS (
+                 UPDATE mentions SET read_at = CASE WHEN $3 THEN COALESCE(read_at, now()) END
+                 WHERE id = $2 AND user_id = $1
+                 RETURNING id, user_id, room_id, message_id, sender_id, excerpt, created_at, read_at
+             )
+             SELECT m.id AS "id!", m.user_id AS "user_id!", m.room_id AS "room_id!", m.message_id AS "message_id!",
+                    m.sender_id, u.name AS "sender_name?", m.excerpt AS "excerpt!", m.created_at AS "created_at!",
+                    m.read_at
+             FROM updated m LEFT JOIN users u ON u.id = m.sender_id"#,
+            user_id, id, read
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // 全部既読にする。更新した件数を返す
+    pub async fn mark_all_read(&self, user_id: i32) -> Result<u64, Error> {
+        let result = sqlx::query!(
+            "UPDATE mentions SET read_at = now() WHERE user_id = $1 AND read_at IS NULL",
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -1 +1,5 @@
-pub mod user_repository;
\ No newline at end of file
+pub mod attachment_repository;
+pub mod audit_repository;
+pub mod mention_repository;
+pub mod room_repository;
+pub mod user_repository;
@@ -0,0 +1,118 @@
+use crate::db::model::audit_event::Actor;
+use crate::db::model::room::Room;
+use crate::db::model::user::UserData;
+use crate::db::repository::audit_repository;
+use serde_json::json;
+use sqlx::{PgPool, Error};
+
+#[derive(Clone)]
+pub struct RoomRepository {
+    pool: PgPool,
+}
+
+impl RoomRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // 一覧取得
+    pub async fn list(&self) -> Result<Vec<Room>, Error> {
+        sqlx::query_as!(
+            Room,
+            "SELECT id, name, updated_at, created_at FROM rooms ORDER BY id"
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // ルームのメンバー
+    pub async fn members(&self, room_id: i32) -> Result<Vec<UserData>, Error> {
+        sqlx::query_as!(
+            UserData,
+            "SELECT u.id, u.name FROM room_users ru JOIN users u ON u.id = ru.user_id
+             WHERE ru.room_id = $1 ORDER BY u.id",
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // ユーザーが所属するルームのID
+    pub async fn room_ids_of(&self, user_id: i32) -> Result<Vec<i32>, Error> {
+        sqlx::query_scalar!(
+            "SELECT DISTINCT room_id FROM room_users WHERE user_id = $1 ORDER BY room_id",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // メンバーかどうか
+    pub async fn is_member(&self, room_id: i32, user_id: i32) -> Result<bool, Error> {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM room_users WHERE room_id = $1 AND user_id = $2) AS "exists!""#,
+            room_id, user_id
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    // 1件取得
+    pub async fn find(&self, id: i32) -> Result<Option<Room>, Error> {
+        sqlx::query_as!(
+            Room,
+            "SELECT id, name, updated_at, created_at FROM rooms WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // 件数
+    pub async fn count(&self) -> Result<i64, Error> {
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM rooms"#)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    // メンバーを外す。監査ログも同じトランザクションで
+    pub async fn remove_member(&self, room_id: i32, user_id: i32, actor: &Actor) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+        let removed = sqlx::query!(
+            "DELETE FROM room_users WHERE room_id = $1 AND user_id = $2",
+            room_id, user_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if removed > 0 {
+            audit_repository::write(
+                &mut *tx, actor, "room.member_removed", Some(&format!("room:{}", room_id)),
+                json!({ "user_id": user_id }),
+            ).await?;
+        }
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    // 削除。メンバーも同じトランザクションで外す
+    pub async fn delete(&self, id: i32, actor: &Actor) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+        let memberships = sqlx::query!("DELETE FROM room_users WHERE room_id = $1", id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let Some(name) = sqlx::query_scalar!("DELETE FROM rooms WHERE id = $1 RETURNING name", id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(0);
+        };
+        audit_repository::write(
+            &mut *tx, actor, "room.delete", Some(&format!("room:{}", id)),
+            json!({ "name": name, "memberships": memberships }),
+        ).await?;
+        tx.commit().await?;
+        Ok(1)
+    }
+}
@@ -1,11 +1,62 @@
-use crate::db::model::user::UserData;
-use sqlx::{PgPool, Error};
+use crate::db::model::audit_event::Actor;
+use crate::db::model::user::{Profile, ProfileChanges, Role, User, UserData};
+use crate::db::repository::audit_repository;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, QueryBuilder, Error};
 
 #[derive(Clone)]
 pub struct UserDataRepository {
     pool: PgPool,
 }
 
+/// Order of [`UserDataRepository::search`] results.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    /// Prefix matches first, then by similarity to the query. Same as `Id` without a query.
+    #[default]
+    Relevance,
+    Id,
+    Name,
+    CreatedAt,
+}
+
+/// Parameters of [`UserDataRepository::search`].
+#[derive(Debug)]
+pub struct UserSearch<'a> {
+    /// Matches names and display names by prefix or trigram similarity.
+    pub query: Option<&'a str>,
+    pub sort: UserSort,
+    pub descending: bool,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+const USER_COLUMNS: &str =
+    "SELECT id, name, display_name, avatar_url, bio, status_message, updated_at, created_at FROM users";
+const COUNT_USERS: &str = "SELECT count(*) FROM users";
+
+// LIKEの特殊文字をエスケープ
+fn like_prefix(query: &str) -> String {
+    let escaped = query.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("{}%", escaped)
+}
+
+// 無効なユーザーは出さない
+fn push_filter<'a>(builder: &mut QueryBuilder<'a, Postgres>, query: Option<&'a str>) {
+    builder.push(" WHERE disabled_at IS NULL");
+    if let Some(query) = query {
+        let prefix = like_prefix(query);
+        let lower = query.to_lowercase();
+        builder
+            .push(" AND (lower(name) LIKE ").push_bind(prefix.clone())
+            .push(" OR lower(name) % ").push_bind(lower.clone())
+            .push(" OR lower(display_name) LIKE ").push_bind(prefix)
+            .push(" OR lower(display_name) % ").push_bind(lower)
+            .push(")");
+    }
+}
+
 impl UserDataRepository {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
@@ -21,6 +72,33 @@ impl UserDataRepository {
         .await
     }
 
+    // 検索・ページング。件数はページに関係なく条件に合う全体数
+    pub async fn search(&self, search: &UserSearch<'_>) -> Result<(Vec<User>, i64), Error> {
+        let direction = if search.descending { "DESC" } else { "ASC" };
+        let mut builder = QueryBuilder::new(USER_COLUMNS);
+        push_filter(&mut builder, search.query);
+        match (search.sort, search.query) {
+            (UserSort::Relevance, Some(query)) => {
+                let lower = query.to_lowercase();
+                builder
+                    .push(" ORDER BY (lower(name) LIKE ").push_bind(like_prefix(query))
+                    .push(") DESC, greatest(similarity(lower(name), ").push_bind(lower.clone())
+                    .push("), similarity(lower(coalesce(display_name, '')), ").push_bind(lower)
+                    .push(")) DESC, id");
+            }
+            (UserSort::Relevance | UserSort::Id, _) => { builder.push(format!(" ORDER BY id {}", direction)); }
+            (UserSort::Name, _) => { builder.push(format!(" ORDER BY name {}, id", direction)); }
+            (UserSort::CreatedAt, _) => { builder.push(format!(" ORDER BY created_at {}, id", direction)); }
+        }
+        builder.push(" LIMIT ").push_bind(search.limit).push(" OFFSET ").push_bind(search.offset);
+        let users = builder.build_query_as::<User>().fetch_all(&self.pool).await?;
+
+        let mut count = QueryBuilder::new(COUNT_USERS);
+        push_filter(&mut count, search.query);
+        let total = count.build_query_scalar::<i64>().fetch_one(&self.pool).await?;
+        Ok((users, total))
+    }
+
     // 1件取得
     pub async fn find(&self, id: i32) -> Result<Option<UserData>, Error> {
         sqlx::query_as!(
@@ -32,45 +110,191 @@ impl UserDataRepository {
         .await
     }
 
-    // 新規作成
-    pub async fn create(&self, name: &str) -> Result<UserData, Error> {
+    // 名前で取得
+    pub async fn find_by_name(&self, name: &str) -> Result<Option<UserData>, Error> {
         sqlx::query_as!(
             UserData,
-            "INSERT INTO users (name) VALUES ($1) RETURNING id, name",
+            "SELECT id, name FROM users WHERE name = $1",
             name
         )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // ルームのメンバーを名前で取得 (@メンションの宛先)。無効なユーザーは除く
+    pub async fn members_named(&self, room_id: i32, names: &[String]) -> Result<Vec<UserData>, Error> {
+        sqlx::query_as!(
+            UserData,
+            "SELECT u.id, u.name FROM users u
+             JOIN room_users ru ON ru.user_id = u.id
+             WHERE ru.room_id = $1 AND u.name = ANY($2) AND u.disabled_at IS NULL
+             ORDER BY u.id",
+            room_id, names
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // プロフィール取得
+    pub async fn profile(&self, id: i32) -> Result<Option<Profile>, Error> {
+        sqlx::query_as!(
+            Profile,
+            "SELECT id, name, display_name, avatar_url, bio, status_message FROM users WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // プロフィール更新。空文字はNULLに戻す
+    pub async fn update_profile(&self, name: &str, changes: &ProfileChanges<'_>) -> Result<Option<Profile>, Error> {
+        sqlx::query_as!(
+            Profile,
+            "UPDATE users SET
+                display_name = NULLIF(COALESCE($1, display_name), ''),
+                avatar_url = NULLIF(COALESCE($2, avatar_url), ''),
+                bio = NULLIF(COALESCE($3, bio), ''),
+                status_message = NULLIF(COALESCE($4, status_message), '')
+             WHERE name = $5
+             RETURNING id, name, display_name, avatar_url, bio, status_message",
+            changes.display_name, changes.avatar_url, changes.bio, changes.status_message, name
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // 新規作成 (password_hash はbcryptでハッシュ済み)
+    pub async fn create(&self, name: &str, password_hash: &str) -> Result<UserData, Error> {
+        sqlx::query_as!(
+            UserData,
+            "INSERT INTO users (name, password) VALUES ($1, $2) RETURNING id, name",
+            name, password_hash
+        )
         .fetch_one(&self.pool)
         .await
     }
 
-    // 更新
-    pub async fn update(&self, id: i32, name: &str) -> Result<Option<UserData>, Error> {
+    // パスワード変更
+    pub async fn set_password(&self, name: &str, password_hash: &str) -> Result<Option<UserData>, Error> {
         sqlx::query_as!(
             UserData,
-            "UPDATE users SET name = $1 WHERE id = $2 RETURNING id, name",
-            name, id
+            "UPDATE users SET password = $1 WHERE name = $2 RETURNING id, name",
+            password_hash, name
         )
         .fetch_optional(&self.pool)
         .await
     }
 
-    // 削除
-    pub async fn delete(&self, id: i32) -> Result<u64, Error> {
-        let result = sqlx::query!("DELETE FROM users WHERE id = $1", id)
-            .execute(&self.pool)
-            .await?;
-        Ok(result.rows_affected())
+    // 無効化・有効化。無効なユーザーはログインできない。監査ログも同じトランザクションで
+    pub async fn set_disabled(&self, name: &str, disabled: bool, actor: &Actor) -> Result<Option<UserData>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let Some(user) = sqlx::query_as!(
+            UserData,
+            "UPDATE users SET disabled_at = CASE WHEN $1 THEN COALESCE(disabled_at, now()) END
+             WHERE name = $2 RETURNING id, name",
+            disabled, name
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let action = if disabled { "user.disable" } else { "user.enable" };
+        audit_repository::write(
+            &mut *tx, actor, action, Some(&format!("user:{}", user.id)),
+            serde_json::json!({ "name": user.name }),
+        ).await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    // ロール変更。トークンに入るので、変更後は再ログインが必要 (セッションの無効化は呼び出し側)。
+    // 監査ログも同じトランザクションで
+    pub async fn set_role(&self, name: &str, role: Role, actor: &Actor) -> Result<Option<UserData>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let Some(user) = sqlx::query_as!(
+            UserData,
+            "UPDATE users SET role = $1 WHERE name = $2 RETURNING id, name",
+            role.as_str(), name
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        audit_repository::write(
+            &mut *tx, actor, "user.set_role", Some(&format!("user:{}", user.id)),
+            serde_json::json!({ "name": user.name, "role": role }),
+        ).await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    // 件数 (全体, 無効)
+    pub async fn count(&self) -> Result<(i64, i64), Error> {
+        let row = sqlx::query!(
+            r#"SELECT count(*) AS "total!", count(disabled_at) AS "disabled!" FROM users"#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((row.total, row.disabled))
+    }
+
+    // 名前の変更。監査ログも同じトランザクションで
+    pub async fn rename(&self, id: i32, name: &str, actor: &Actor) -> Result<Option<UserData>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let Some(from) = sqlx::query_scalar!("SELECT name FROM users WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let renamed = sqlx::query_as!(
+            UserData,
+            "UPDATE users SET name = $1 WHERE id = $2 RETURNING id, name",
+            name, id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        audit_repository::write(
+            &mut *tx, actor, "user.rename", Some(&format!("user:{}", id)),
+            serde_json::json!({ "from": from, "to": renamed.name }),
+        ).await?;
+        tx.commit().await?;
+        Ok(Some(renamed))
+    }
+
+    // 削除。ルームの所属も同じトランザクションで消し、監査ログに残す
+    pub async fn delete(&self, id: i32, actor: &Actor) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+        let memberships = sqlx::query!("DELETE FROM room_users WHERE user_id = $1", id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let Some(name) = sqlx::query_scalar!("DELETE FROM users WHERE id = $1 RETURNING name", id)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(0);
+        };
+        audit_repository::write(
+            &mut *tx, actor, "user.delete", Some(&format!("user:{}", id)),
+            serde_json::json!({ "name": name, "memberships": memberships }),
+        ).await?;
+        tx.commit().await?;
+        Ok(1)
     }
 
-    pub async fn find_with_password_by_name(&self, name: &str) -> Result<Option<(UserData, String)>, sqlx::Error> {
+    pub async fn find_with_password_by_name(&self, name: &str) -> Result<Option<(UserData, String, Role)>, sqlx::Error> {
         let row = sqlx::query!(
-            "SELECT id, name, password FROM users WHERE name = $1",
+            "SELECT id, name, password, role FROM users WHERE name = $1 AND disabled_at IS NULL",
             name
         )
         .fetch_optional(&self.pool)
         .await?;
     
-        Ok(row.map(|r| (UserData { id: r.id, name: r.name }, r.password)))
+        // roleはCHECK制約で user / admin のどちらか
+        Ok(row.map(|r| (UserData { id: r.id, name: r.name }, r.password, r.role.parse().unwrap_or_default())))
         // r.password はすでに String 型
     }
 }
\ No newline at end of file
@@ -0,0 +1,62 @@
+use sqlx::PgPool;
+
+use crate::db::repository::user_repository::UserDataRepository;
+
+const SERVICES: [&str; 3] = ["Eat-in", "Take-out", "Reservation"];
+const ROOMS: [&str; 3] = ["Room A", "Room B", "Room C"];
+const USERS: [&str; 3] = ["Alice", "Bob", "Charlie"];
+/// Password of every seeded user.
+pub const PASSWORD: &str = "password";
+
+/// Rows inserted by [`run`], existing ones are left alone.
+#[derive(Debug, Default)]
+pub struct SeedSummary {
+    pub services: u64,
+    pub rooms: u64,
+    pub users: u64,
+    pub memberships: u64,
+}
+
+/// Development data (formerly `dummy_data.sql`): three services, three rooms
+/// and three users, each the member of one room. Safe to run repeatedly.
+pub async fn run(pool: &PgPool) -> Result<SeedSummary, sqlx::Error> {
+    let mut summary = SeedSummary::default();
+    for name in SERVICES {
+        summary.services += sqlx::query("INSERT INTO services (name) SELECT $1 WHERE NOT EXISTS (SELECT 1 FROM services WHERE name = $1)")
+            .bind(name)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    }
+    for name in ROOMS {
+        summary.rooms += sqlx::query("INSERT INTO rooms (name) SELECT $1 WHERE NOT EXISTS (SELECT 1 FROM rooms WHERE name = $1)")
+            .bind(name)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    }
+
+    let users = UserDataRepository::new(pool.clone());
+    let hash = bcrypt::hash(PASSWORD, bcrypt::DEFAULT_COST).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    for name in USERS {
+        if users.find_by_name(name).await?.is_none() {
+            users.create(name, &hash).await?;
+            summary.users += 1;
+        }
+    }
+
+    // Alice in Room A, Bob in Room B, Charlie in Room C
+    for (user, room) in USERS.iter().zip(ROOMS) {
+        summary.memberships += sqlx::query(
+            "INSERT INTO room_users (room_id, user_id)
+             SELECT r.id, u.id FROM rooms r, users u WHERE r.name = $1 AND u.name = $2
+             ON CONFLICT (room_id, user_id) DO NOTHING",
+        )
+        .bind(room)
+        .bind(user)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    }
+    Ok(summary)
+}
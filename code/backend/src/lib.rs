@@ -0,0 +1,10 @@
+//! Shared by the API server (`chat-sample`) and the admin CLI (`chat-admin`).
+pub mod api;
+pub mod bus;
+pub mod db;
+pub mod library;
+pub mod preview;
+pub mod settings;
+pub mod storage;
+
+pub const PROJECT_PATH: &str = env!("CARGO_MANIFEST_DIR");
@@ -1,44 +1,53 @@
-use chrono::Local;
-use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::Path;
-use lazy_static::lazy_static;
 
-// Path to log file
-use crate::PROJECT_PATH;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+use tracing_subscriber::{fmt, EnvFilter};
 
-lazy_static! {
-    pub static ref LOG_PATH: String = format!("{}/log/actix.log", PROJECT_PATH);
+use crate::library::rolling_file::RollingFile;
+use crate::settings::{LogFormat, LogSettings};
+
+/// Flushes the buffered lines when dropped, keep it alive until exit.
+pub struct LogGuard {
+    _guards: Vec<WorkerGuard>,
 }
 
-/// List of different types of log headers.
-#[allow(dead_code)]
-pub enum Header {
-    SUCCESS,
-    INFO,
-    WARNING,
-    ERROR
+/// Every output gets its own writer thread behind a bounded queue. When the
+/// queue is full lines are dropped, so logging never blocks an actix worker.
+fn non_blocking<W: Write + Send + 'static>(writer: W, settings: &LogSettings, name: &str) -> (NonBlocking, WorkerGuard) {
+    NonBlockingBuilder::default()
+        .buffered_lines_limit(settings.queue_size)
+        .lossy(true)
+        .thread_name(name)
+        .finish(writer)
 }
 
-/// Logs a message to the console.
-pub fn log(header: Header, message: &str) {
-    // Type of message to log
-    let header = match header {
-        Header::SUCCESS => "SUCCESS",
-        Header::INFO => "INFO",
-        Header::WARNING => "WARNING",
-        Header::ERROR => "ERROR"
+/// Installs the global tracing subscriber, writing to stdout and, when
+/// `log.dir` is set, to a rotated file. Records of the `log` crate (actix,
+/// sqlx, ...) are forwarded to it as well.
+pub fn init(settings: &LogSettings) -> LogGuard {
+    let (stdout, stdout_guard) = non_blocking(std::io::stdout(), settings, "log-stdout");
+    let mut guards = vec![stdout_guard];
+    let writer = match &settings.file {
+        Some(file) => {
+            let (file, file_guard) = non_blocking(RollingFile::new(file.clone()), settings, "log-file");
+            guards.push(file_guard);
+            BoxMakeWriter::new(stdout.and(file))
+        }
+        None => BoxMakeWriter::new(stdout),
     };
 
-    // Print the log to the console
-    println!("[{}] {} {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), header, message);
-
-    // Write the log to a file
-    if Path::new(&*LOG_PATH).exists() {
-        let mut log_file = OpenOptions::new().append(true).open(&*LOG_PATH).unwrap();
-        writeln!(log_file, "[{}] {} {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), header, message).unwrap();
-    } else {
-        let mut log_file = OpenOptions::new().create_new(true).append(true).open(&*LOG_PATH).unwrap();
-        writeln!(log_file, "[{}] {} {}", Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), header, message).unwrap();
+    let filter = EnvFilter::new(&settings.level);
+    let builder = fmt().with_env_filter(filter).with_target(true).with_writer(writer);
+    let result = match settings.format {
+        // One JSON object per line, with the fields of the current request span
+        // (request_id, user_id, room_id) flattened into `span`
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).try_init(),
+        // No colour codes in the file
+        LogFormat::Pretty => builder.with_ansi(settings.file.is_none()).try_init(),
+    };
+    if let Err(e) = result {
+        eprintln!("Logging is already initialized: {}", e);
     }
+    LogGuard { _guards: guards }
 }
@@ -0,0 +1,91 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, ImageReader, Limits};
+
+/// Images larger than this on either side are not thumbnailed, decoding
+/// them could take more memory than the upload itself suggests.
+const MAX_IMAGE_SIDE: u32 = 10_000;
+/// Memory a decode may allocate. A few KiB of compressed data can claim a
+/// huge image, this is what stops it (10_000 x 10_000 RGBA alone is 400 MB).
+const MAX_DECODE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// MIME type of an upload, detected from its content. The type the client
+/// declared is only trusted for plain text, which has no signature.
+pub fn detect_type(data: &[u8], declared: Option<&str>) -> String {
+    if let Some(kind) = infer::get(data) {
+        return kind.mime_type().to_string();
+    }
+    let declared_text = declared.is_some_and(|declared| declared.split(';').next().unwrap_or_default().trim() == "text/plain");
+    if declared_text && std::str::from_utf8(data).is_ok() {
+        return "text/plain".to_string();
+    }
+    "application/octet-stream".to_string()
+}
+
+/// A thumbnail of an image.
+pub struct Thumbnail {
+    pub png: Vec<u8>,
+    /// Size of the original.
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Scales an image down to fit `max_px` x `max_px` (never up) and encodes it
+/// as PNG. CPU bound, run it off the async workers.
+pub fn thumbnail(data: &[u8], max_px: u32) -> Result<Thumbnail, image::ImageError> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let (width, height) = (image.width(), image.height());
+    let scaled = if width > max_px || height > max_px { image.thumbnail(max_px, max_px) } else { image };
+    let mut png = Vec::new();
+    scaled.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(Thumbnail { png, width, height })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn detects_by_content_not_by_declaration() {
+        assert_eq!(detect_type(&png(1, 1), Some("text/plain")), "image/png");
+        assert_eq!(detect_type(b"hello", Some("text/plain; charset=utf-8")), "text/plain");
+        assert_eq!(detect_type(b"<html><script>", Some("image/png")), "text/html");
+        assert_eq!(detect_type(b"\x00\x01binary", Some("image/png")), "application/octet-stream");
+        assert_eq!(detect_type(&[0xff, 0xfe, 0x00], Some("text/plain")), "application/octet-stream");
+    }
+
+    #[test]
+    fn scales_down_keeping_the_aspect_ratio() {
+        let thumb = thumbnail(&png(400, 200), 100).unwrap();
+        assert_eq!((thumb.width, thumb.height), (400, 200));
+        let scaled = image::load_from_memory(&thumb.png).unwrap();
+        assert_eq!(scaled.dimensions(), (100, 50));
+
+        let small = thumbnail(&png(20, 10), 100).unwrap();
+        assert_eq!(image::load_from_memory(&small.png).unwrap().dimensions(), (20, 10));
+        assert!(thumbnail(b"not an image", 100).is_err());
+    }
+
+    #[test]
+    fn refuses_images_that_decode_too_large() {
+        // 8000 x 8000, within the side limit, from a 40 byte file
+        let mut gif = b"GIF89a\x40\x1f\x40\x1f\x00\x00\x00".to_vec();
+        gif.extend_from_slice(b"\x2c\x00\x00\x00\x00\x40\x1f\x40\x1f\x00\x02\x02\x44\x01\x00\x3b");
+        assert!(matches!(thumbnail(&gif, 100), Err(image::ImageError::Limits(_))), "{:?}", thumbnail(&gif, 100).err());
+    }
+}
@@ -0,0 +1,44 @@
+/// Longest user name, see `validate_name`.
+const MAX_NAME_CHARS: usize = 50;
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Names mentioned as `@name` in `msg`, in order, without duplicates, at
+/// most `max`. The `@` has to start a word, so e-mail addresses are not
+/// mentions, and a trailing `.` ends the sentence rather than the name.
+pub fn mentioned_names(msg: &str, max: usize) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut previous = None;
+    for (i, c) in msg.char_indices() {
+        let starts_word = previous.is_none_or(|p: char| !is_name_char(p) && p != '@');
+        previous = Some(c);
+        if c != '@' || !starts_word || names.len() == max {
+            continue;
+        }
+        let rest = &msg[i + 1..];
+        let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let name = rest[..end].trim_end_matches('.');
+        if !name.is_empty() && name.chars().count() <= MAX_NAME_CHARS && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mentions_but_not_addresses() {
+        assert_eq!(
+            mentioned_names("@alice hi, (@bob.smith) and @alice. mail carol@example.com or @@dave, @日本", 10),
+            ["alice", "bob.smith", "日本"]
+        );
+        assert_eq!(mentioned_names("@a @b @c", 2), ["a", "b"]);
+        assert!(mentioned_names("@ @. nobody", 10).is_empty());
+        assert!(mentioned_names(&format!("@{}", "x".repeat(51)), 10).is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use prometheus::{
+    histogram_opts, opts, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, Registry,
+    TextEncoder,
+};
+
+/// Process wide metrics, rendered by `GET /metrics`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_duration: HistogramVec,
+    pub auth_tokens_rejected: IntCounterVec,
+    pub sse_clients: IntGauge,
+    pub sse_lag_events: IntCounter,
+    pub sse_skipped: IntCounter,
+    pub sse_backfilled: IntCounter,
+    pub bus_publish: IntCounterVec,
+    pub bus_publish_duration: Histogram,
+    pub bus_consumed: IntCounter,
+    pub bus_duplicates: IntCounter,
+    pub bus_dead_lettered: IntCounter,
+    pub bus_dropped: IntCounter,
+    pub bus_ack_failures: IntCounter,
+    pub redis_duration: HistogramVec,
+    pub link_previews: IntCounterVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle: IntGauge,
+    pub db_pool_max: IntGauge,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(registry: &Registry, metric: T) -> T {
+    registry.register(Box::new(metric.clone())).expect("metric registered twice");
+    metric
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let r = Registry::new_custom(Some("chat".to_string()), None).expect("valid metrics prefix");
+        // Redis and the bus answer in milliseconds, HTTP includes DB queries and bcrypt
+        let fast = vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+        Metrics {
+            http_requests: register(&r, IntCounterVec::new(
+                opts!("http_requests_total", "HTTP requests by route pattern and status"),
+                &["method", "route", "status"],
+            ).unwrap()),
+            http_duration: register(&r, HistogramVec::new(
+                histogram_opts!("http_request_duration_seconds", "Time until the response head is sent"),
+                &["method", "route"],
+            ).unwrap()),
+            auth_tokens_rejected: register(&r, IntCounterVec::new(
+                opts!("auth_tokens_rejected_total", "Bearer tokens rejected by the JWT middleware"),
+                &["reason"],
+            ).unwrap()),
+            // Not labelled by room: room ids are unbounded, per-room counts are in the admin stats
+            sse_clients: register(&r, IntGauge::new("sse_clients", "Open SSE streams, room and personal").unwrap()),
+            sse_lag_events: register(&r, IntCounter::new("sse_lag_events_total", "Times an SSE client fell behind its room").unwrap()),
+            sse_skipped: register(&r, IntCounter::new("sse_skipped_messages_total", "Messages lagging SSE clients missed").unwrap()),
+            sse_backfilled: register(&r, IntCounter::new("sse_backfilled_messages_total", "Missed messages resent from room history").unwrap()),
+            bus_publish: register(&r, IntCounterVec::new(
+                opts!("bus_publish_total", "Messages published to the bus by outcome"),
+                &["outcome"],
+            ).unwrap()),
+            bus_publish_duration: register(&r, Histogram::with_opts(
+                histogram_opts!("bus_publish_duration_seconds", "Time to publish a message to the bus", fast.clone()),
+            ).unwrap()),
+            bus_consumed: register(&r, IntCounter::new("bus_consumed_total", "Messages received from the bus").unwrap()),
+            bus_duplicates: register(&r, IntCounter::new("bus_duplicates_total", "Redelivered messages dropped").unwrap()),
+            bus_dead_lettered: register(&r, IntCounter::new("bus_dead_lettered_total", "Messages moved to the dead-letter list").unwrap()),
+            bus_dropped: register(&r, IntCounter::new("bus_dropped_total", "Messages lost by the bus before they were consumed").unwrap()),
+            bus_ack_failures: register(&r, IntCounter::new("bus_ack_failures_total", "Failed acks and nacks").unwrap()),
+            redis_duration: register(&r, HistogramVec::new(
+                histogram_opts!("redis_command_duration_seconds", "Latency of redis commands sent through the actor", fast),
+                &["command", "outcome"],
+            ).unwrap()),
+            link_previews: register(&r, IntCounterVec::new(
+                opts!("link_previews_total", "Link previews looked up by outcome"),
+                &["outcome"],
+            ).unwrap()),
+            db_pool_connections: register(&r, IntGauge::new("db_pool_connections", "Open database connections").unwrap()),
+            db_pool_idle: register(&r, IntGauge::new("db_pool_idle_connections", "Idle database connections").unwrap()),
+            db_pool_max: register(&r, IntGauge::new("db_pool_max_connections", "Configured maximum of database connections").unwrap()),
+            registry: r,
+        }
+    }
+
+    /// Everything in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(error = %e, "Failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Runs a redis command future and records its latency.
+pub async fn time_redis<T, E, F>(command: &'static str, fut: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let result = fut.await;
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    METRICS
+        .redis_duration
+        .with_label_values(&[command, outcome])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
@@ -1 +1,6 @@
 pub mod logger;
+pub mod media;
+pub mod mentions;
+pub mod metrics;
+pub mod rolling_file;
+pub mod shutdown;
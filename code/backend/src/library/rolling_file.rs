@@ -0,0 +1,196 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+
+use crate::settings::{LogFileSettings, Rotation};
+
+/// Log file that is rotated when it grows past `max_bytes` or when the
+/// hour/day changes. Rotated files are renamed to `<file_name>.<timestamp>`
+/// and only the newest `max_files` of them are kept.
+///
+/// Errors are returned to the caller (the non-blocking worker, which drops
+/// them) and the file is reopened on the next write, so a full disk or a
+/// deleted directory only loses lines instead of taking the process down.
+pub struct RollingFile {
+    settings: LogFileSettings,
+    file: Option<File>,
+    written: u64,
+    period: String,
+    failing: bool,
+}
+
+impl RollingFile {
+    pub fn new(settings: LogFileSettings) -> Self {
+        RollingFile { settings, file: None, written: 0, period: String::new(), failing: false }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.settings.dir.join(&self.settings.file_name)
+    }
+
+    fn period_at(&self, time: DateTime<Local>) -> String {
+        match self.settings.rotation {
+            Rotation::Never => String::new(),
+            Rotation::Hourly => time.format("%Y-%m-%d-%H").to_string(),
+            Rotation::Daily => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    fn current_period(&self) -> String {
+        self.period_at(Local::now())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        fs::create_dir_all(&self.settings.dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(self.path())?;
+        let metadata = file.metadata()?;
+        self.written = metadata.len();
+        // The period of what is already in the file, so one left over from an
+        // earlier period (before a restart) is rotated instead of appended to
+        self.period = match metadata.modified() {
+            Ok(modified) if self.written > 0 => self.period_at(modified.into()),
+            _ => self.current_period(),
+        };
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let path = self.path();
+        if path.exists() {
+            let rotated = format!("{}.{}", self.settings.file_name, Local::now().format("%Y%m%dT%H%M%S%.3f"));
+            fs::rename(&path, self.settings.dir.join(rotated))?;
+        }
+        self.prune()?;
+        self.open()
+    }
+
+    /// Deletes the oldest rotated files beyond `max_files`. The timestamp
+    /// suffix sorts chronologically, so sorting by name is enough.
+    fn prune(&self) -> io::Result<()> {
+        let prefix = format!("{}.", self.settings.file_name);
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&self.settings.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.settings.max_files);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let period = self.current_period();
+        if self.file.is_none() {
+            self.open()?;
+        }
+        let too_big = self.settings.max_bytes > 0
+            && self.written > 0
+            && self.written + buf.len() as u64 > self.settings.max_bytes;
+        if too_big || period != self.period {
+            self.rotate()?;
+            self.period = period;
+        }
+        let file = self.file.as_mut().ok_or_else(|| io::Error::other("log file is not open"))?;
+        file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.write_line(buf) {
+            Ok(written) => {
+                self.failing = false;
+                Ok(written)
+            }
+            Err(e) => {
+                // Report once per outage, not once per line
+                if !self.failing {
+                    eprintln!("Failed to write {}: {}", self.path().display(), e);
+                    self.failing = true;
+                }
+                self.file = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(name: &str, max_bytes: u64, max_files: usize) -> LogFileSettings {
+        let dir = std::env::temp_dir().join(format!("chat-sample-{}-{}", name, rand::random::<u64>()));
+        LogFileSettings { dir, file_name: "test.log".to_string(), max_bytes, rotation: Rotation::Never, max_files }
+    }
+
+    fn files(settings: &LogFileSettings) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&settings.dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_max_files() {
+        let settings = settings("size", 10, 2);
+        let mut file = RollingFile::new(settings.clone());
+        for _ in 0..5 {
+            file.write_all(b"0123456789").unwrap();
+            // Rotated names have millisecond timestamps
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let names = files(&settings);
+        assert_eq!(names.len(), 3, "{:?}", names);
+        assert_eq!(names[0], "test.log");
+        assert_eq!(fs::read(settings.dir.join("test.log")).unwrap(), b"0123456789");
+        fs::remove_dir_all(&settings.dir).unwrap();
+    }
+
+    #[test]
+    fn counts_the_existing_file_after_a_restart() {
+        let settings = settings("restart", 10, 5);
+        RollingFile::new(settings.clone()).write_all(b"first\n").unwrap();
+        RollingFile::new(settings.clone()).write_all(b"second\n").unwrap();
+        assert_eq!(files(&settings).len(), 2);
+        assert_eq!(fs::read(settings.dir.join("test.log")).unwrap(), b"second\n");
+        fs::remove_dir_all(&settings.dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_a_file_left_from_an_earlier_period() {
+        let settings = LogFileSettings { rotation: Rotation::Daily, ..settings("period", 0, 5) };
+        RollingFile::new(settings.clone()).write_all(b"yesterday\n").unwrap();
+        let yesterday = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 24 * 60 * 60);
+        File::options().append(true).open(settings.dir.join("test.log")).unwrap().set_modified(yesterday).unwrap();
+
+        // Restarted: the old file is rotated, not appended to
+        RollingFile::new(settings.clone()).write_all(b"today\n").unwrap();
+        assert_eq!(files(&settings).len(), 2);
+        assert_eq!(fs::read(settings.dir.join("test.log")).unwrap(), b"today\n");
+
+        // Same period: appended
+        RollingFile::new(settings.clone()).write_all(b"again\n").unwrap();
+        assert_eq!(files(&settings).len(), 2);
+        assert_eq!(fs::read(settings.dir.join("test.log")).unwrap(), b"today\nagain\n");
+        fs::remove_dir_all(&settings.dir).unwrap();
+    }
+}
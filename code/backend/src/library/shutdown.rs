@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// A stop signal that, once set, stays set. The process-wide one is set when
+/// shutdown starts and makes readiness report 503; the subscriber gets its
+/// own, set only after SSE clients were told to reconnect.
+pub struct Shutdown {
+    tx: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (tx, _) = watch::channel(false);
+        Shutdown { tx }
+    }
+}
+
+impl Shutdown {
+    pub fn begin(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once `begin` has been called, immediately if it already was.
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        let _ = rx.wait_for(|draining| *draining).await;
+    }
+
+    /// Begins draining, then runs `stop` after `pre_stop_delay`. Load balancers
+    /// only notice the 503 from /readyz at their next probe, so streams are not
+    /// closed and new connections not refused before they stopped routing here.
+    pub async fn drain<T>(&self, pre_stop_delay: Duration, stop: impl Future<Output = T>) -> T {
+        self.begin();
+        tracing::info!(delay_secs = pre_stop_delay.as_secs(), "Waiting for load balancers before stopping");
+        tokio::time::sleep(pre_stop_delay).await;
+        stop.await
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM (what Kubernetes sends before killing a pod).
+pub async fn signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => tracing::info!("Received Ctrl-C"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn readiness_drops_before_the_pre_stop_delay_and_stop_runs_after_it() {
+        let shutdown = Shutdown::default();
+        let start = Instant::now();
+        let not_ready = async {
+            shutdown.wait().await;
+            start.elapsed()
+        };
+        let stopped = shutdown.drain(Duration::from_secs(5), async { (shutdown.is_draining(), start.elapsed()) });
+
+        let (not_ready, (draining, stopped)) = tokio::join!(not_ready, stopped);
+        assert_eq!(not_ready, Duration::ZERO);
+        assert!(draining);
+        assert_eq!(stopped, Duration::from_secs(5));
+    }
+}
@@ -1,97 +1,210 @@
 use actix::prelude::*;
-use actix_cors::Cors;
 use actix_web::{web::Data, App, HttpServer};
-use actix_web::middleware::Logger;
 use dotenv::dotenv;
-use google_cloud_pubsub::client::{Client, ClientConfig};
-use tokio_stream::StreamExt;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tokio::time::{sleep, Duration};
-
-mod api;
-mod db;
-mod library;
+use std::time::Duration;
 
+use chat_sample::{api, bus, db, library, preview, storage};
 use library::logger;
-
-const PROJECT_PATH: &'static str = env!("CARGO_MANIFEST_DIR");
+use library::shutdown::{self, Shutdown};
+use chat_sample::settings::Settings;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    logger::log(logger::Header::INFO, "API server started");
-    
     // Load environment variables from .env file
     dotenv().ok();
+    match std::env::args().nth(1).as_deref() {
+        None | Some("serve") => {}
+        Some("migrate") => return migrate().await,
+        Some(other) => {
+            eprintln!("Unknown command '{}', expected serve or migrate", other);
+            return Err(std::io::Error::other(format!("unknown command {}", other)));
+        }
+    }
+    // settings.toml with environment overrides, every problem is reported at once
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            // Logging is configured by the settings, so it is not up yet
+            eprintln!("{}", e);
+            return Err(std::io::Error::other(e));
+        }
+    };
+    let _log_guard = logger::init(&settings.log);
+    api::jwt::jwt::init(&settings.auth.jwt_secret);
+    tracing::info!(pod_name = %settings.pod_name, "API server started");
     // Create the connection pool
-    let pool = db::pool::get_db_pool().await;
-    // Broadcasting channel for SSE 
-    let (tx, _rx) = broadcast::channel::<String>(2000);
-    // PubSubクライアント作成
-    let topic_id = std::env::var("TOPIC_NAME").expect("TOPIC_NAME must be set");
-    let subscribe_id = std::env::var("SUBSCRIBE_NAME").expect("SUBSCRIBE_NAME must be set");
-    let config = ClientConfig::default().with_auth().await.expect("Failed to create PubSub client config");
-    let pubsub_client = Client::new(config).await.expect("Failed to create PubSub client");
-    pubsub_client.topic(&topic_id);
-    let tx_clone = tx.clone();
-    let topic_id = std::env::var("TOPIC_NAME").expect("TOPIC_NAME must be set");
-    let topic = pubsub_client.topic(&topic_id);
-    // Start publisher.
-    let publisher = topic.new_publisher(None);
-    let subscription = pubsub_client.clone().subscription(&subscribe_id);
-    tokio::spawn(async move {
-        // Pull型ストリーム
-        let mut stream = subscription.subscribe(None).await.expect("failed to subscribe");
-        while let Some(message) = stream.next().await {
-            if let Ok(data) = String::from_utf8(message.message.data.clone()) {
-                let _ = tx_clone.send(data);
+    let pool = db::pool::get_db_pool(&settings.database).await;
+    if settings.database.migrate_on_startup {
+        match db::migrate::run(&pool).await {
+            Ok(version) => tracing::info!(version, "Database schema is up to date"),
+            Err(e) => {
+                tracing::error!(error = %e, "Migration failed");
+                return Err(std::io::Error::other(e));
             }
-            logger::log(logger::Header::INFO, "stream loop");
-            match message.ack().await {
-                Ok(_) => {}
-                Err(e) => {
-                    logger::log(logger::Header::ERROR, &format!("Failed to ack message: {}", e));
-                    // If ack fails, wait for a while before retrying
-                    sleep(Duration::from_secs(5)).await;
-                }
+        }
+    }
+    // Broadcasting channel for SSE, with a history to backfill lagging clients
+    let broadcaster = Arc::new(api::broadcaster::Broadcaster::new(settings.sse.clone()));
+    // Rooms nobody listens to any more are dropped with their history
+    let sweeper = broadcaster.clone();
+    let mut sweep = tokio::time::interval(settings.sse.room_idle_ttl);
+    actix_web::rt::spawn(async move {
+        loop {
+            sweep.tick().await;
+            let evicted = sweeper.evict_idle_rooms();
+            if evicted > 0 {
+                tracing::debug!(evicted, "Evicted idle SSE rooms");
             }
         }
     });
 
     // Redis Cluster
-    let redis_url = &std::env::var("REDIS_URL").expect("REDIS_URL must be set");
-    let actor = api::redis::RedisActor::new(vec![redis_url]).await;
+    let actor = api::redis::RedisActor::new(vec![&settings.redis.url]).await;
     let addr = actor.start();
 
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            //.allowed_origin("http://localhost")
-            .allowed_methods(vec!["GET", "POST", "DELETE", "OPTIONS"])
-            .allowed_headers(vec!["Authorization", "Content-Type", "Accept", "Content-Type"])
-            .supports_credentials()
-            .max_age(60 * 60 * 24); // 1 day
-                                    /*
-                                    This sets the max_age to 1 day, meaning that
-                                    once the browser makes a successful preflight request to the server,
-                                    it can cache the results of that request for up to 3 days.
-                                    Subsequent requests to the same resource within this time frame won't trigger another preflight request;
-                                    the browser will use the cached results instead.
-                                    */
+    // Message bus (memory, redis, redis-streams or pubsub)
+    let message_bus = match bus::from_settings(&settings, addr.clone()).await {
+        Ok(message_bus) => message_bus,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to set up the message bus");
+            return Err(std::io::Error::other(e));
+        }
+    };
+    // Attachment contents (local directory or S3)
+    let object_store = match storage::from_settings(&settings.attachments) {
+        Ok(object_store) => object_store,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to set up the object store");
+            return Err(std::io::Error::other(e));
+        }
+    };
+    // Link previews of URLs in messages, fetched after publishing
+    let link_previewer = match preview::LinkPreviewer::new(&settings.link_preview, addr.clone()) {
+        Ok(link_previewer) => Arc::new(link_previewer),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to set up link previews");
+            return Err(std::io::Error::other(e));
+        }
+    };
+    let shutdown = Arc::new(Shutdown::default());
+    // Subscriber loop, restarted with backoff whenever the bus stream ends
+    let subscriber_health = Arc::new(bus::subscriber::SubscriberHealth::default());
+    let subscriber = bus::subscriber::Subscriber::new(
+        message_bus.clone(),
+        broadcaster.clone(),
+        Arc::new(bus::dead_letter::DeadLetterSink::new(
+            addr.clone(),
+            &settings.bus.dead_letter_key,
+            settings.bus.dead_letter_max_len,
+        )),
+        Arc::new(bus::dedup::Deduplicator::for_pod(addr.clone(), &settings.pod_name, settings.bus.dedup_ttl_secs)),
+        subscriber_health.clone(),
+        settings.bus.max_delivery_attempts,
+    );
+    // Stopped only once SSE clients were told to reconnect, so they get
+    // messages until then
+    let subscriber_stop = Arc::new(Shutdown::default());
+    let subscriber_task = tokio::spawn(subscriber.run(subscriber_stop.clone()));
+
+    let cors_settings = settings.cors.clone();
+    let json_limit = settings.server.json_limit_bytes;
+    let app_pool = pool.clone();
+    let app_broadcaster = broadcaster.clone();
+    let app_message_bus = message_bus.clone();
+    let app_shutdown = shutdown.clone();
+    let published = Arc::new(bus::dedup::Deduplicator::for_publishes(addr.clone(), settings.bus.dedup_ttl_secs));
+    let attachment_settings = settings.attachments.clone();
+    let trusted_proxies = api::client_ip::TrustedProxies(settings.server.trusted_proxies.clone());
+    let server = HttpServer::new(move || {
+        let cors = api::middleware::cors_middleware::cors(&cors_settings);
 
         // Start the API server
         App::new()
-            .wrap(Logger::default())
+            .wrap(api::middleware::metrics_middleware::MetricsMiddleware)
             .wrap(cors)
-            .app_data(Data::new(pool.clone()))
-            .app_data(Data::new(tx.clone()))
+            // Outermost, so CORS rejections are logged with a request id too
+            .wrap(api::middleware::request_id_middleware::RequestIdMiddleware)
+            .app_data(Data::new(app_pool.clone()))
+            .app_data(Data::new(db::repository::user_repository::UserDataRepository::new(app_pool.clone())))
+            .app_data(Data::new(db::repository::room_repository::RoomRepository::new(app_pool.clone())))
+            .app_data(Data::new(db::repository::audit_repository::AuditRepository::new(app_pool.clone())))
+            .app_data(Data::new(db::repository::attachment_repository::AttachmentRepository::new(app_pool.clone())))
+            .app_data(Data::new(db::repository::mention_repository::MentionRepository::new(app_pool.clone())))
+            .app_data(Data::from(object_store.clone()))
+            .app_data(Data::new(attachment_settings.clone()))
+            .app_data(Data::new(trusted_proxies.clone()))
+            .app_data(Data::from(app_broadcaster.clone()))
             .app_data(Data::new(addr.clone()))
-            .app_data(Data::new(publisher.clone()))
-            .service(api::api_handler::api_scope())
+            .app_data(Data::from(app_message_bus.clone()))
+            .app_data(Data::from(link_previewer.clone()))
+            .app_data(Data::from(published.clone()))
+            .app_data(Data::from(subscriber_health.clone()))
+            .app_data(Data::from(app_shutdown.clone()))
+            .configure(api::api_handler::root_routes)
+            .service(api::api_handler::api_scope(json_limit))
     })
-    .bind("0.0.0.0:8080")?
-    .workers(20)
-    .run()
-    .await
+    .bind(&settings.server.bind_address)?
+    .workers(settings.server.workers)
+    .shutdown_timeout(settings.server.shutdown_timeout_secs)
+    // Signals are handled below, so streams and the subscriber are drained first
+    .disable_signals()
+    .run();
+    let server_handle = server.handle();
+    let mut server_task = actix_web::rt::spawn(server);
+
+    tokio::select! {
+        _ = shutdown::signal() => {}
+        result = &mut server_task => return result.map_err(std::io::Error::other)?,
+    }
+
+    tracing::info!("Shutting down");
+    // /readyz answers 503 from now on, streams and the subscriber go on
+    // until the pre-stop delay is over
+    let pre_stop_delay = Duration::from_secs(settings.server.pre_stop_delay_secs);
+    let deadline = Duration::from_secs(settings.server.shutdown_timeout_secs);
+    let drained = shutdown
+        .drain(pre_stop_delay, async {
+            let clients = broadcaster.restart_clients();
+            tracing::info!(clients, "Told SSE clients to reconnect");
+            subscriber_stop.begin();
+            tokio::time::timeout(deadline, async {
+                // Finishes (and acks) the message in hand
+                if let Err(e) = subscriber_task.await {
+                    tracing::error!(error = %e, "Subscriber task failed");
+                }
+                // Stops accepting, waits for in-flight requests
+                server_handle.stop(true).await;
+                message_bus.close().await;
+                pool.close().await;
+            })
+            .await
+        })
+        .await;
+    if drained.is_err() {
+        tracing::warn!(deadline_secs = deadline.as_secs(), "Shutdown deadline exceeded, stopping anyway");
+        server_handle.stop(false).await;
+    }
+    tracing::info!("Shutdown complete");
+    server_task.await.map_err(std::io::Error::other)?
+}
+
+/// `chat-sample migrate`: applies pending migrations and exits. Only needs
+/// the database settings.
+async fn migrate() -> std::io::Result<()> {
+    let database = Settings::load_database().map_err(|e| {
+        eprintln!("{}", e);
+        std::io::Error::other(e)
+    })?;
+    let pool = db::pool::get_db_pool(&database).await;
+    match db::migrate::run(&pool).await {
+        Ok(version) => {
+            println!("Database schema is at version {}", version);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            Err(std::io::Error::other(e))
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+
+/// Why a URL or address may not be fetched. Returned through reqwest by the
+/// resolver and the redirect policy, where it is found again by type.
+#[derive(Debug)]
+pub struct Blocked(pub String);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// Whether `ip` is reachable on the public internet. Fetching anything else
+/// on behalf of a chat message would let users probe the cluster network,
+/// cloud metadata endpoints (169.254.169.254) or the pod itself.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    // IPv4-mapped (::ffff:a.b.c.d) and NAT64 (64:ff9b::a.b.c.d) reach the IPv4 address
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+    let segments = ip.segments();
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // IPv4-compatible, ::a.b.c.d
+        || segments[..6] == [0; 6]
+        // Unique local, fc00::/7
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link local and the old site local, fe80::/10 and fec0::/10
+        || (segments[0] & 0xff80) == 0xfe80
+        // Documentation, 2001:db8::/32
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        // Teredo 2001::/32 and 6to4 2002::/16 tunnel to arbitrary IPv4 addresses
+        || (segments[0] == 0x2001 && segments[1] == 0)
+        || segments[0] == 0x2002)
+}
+
+/// Whether `ip` may be connected to. Loopback is only ever allowed in tests.
+fn is_allowed(ip: IpAddr, allow_loopback: bool) -> bool {
+    is_public(ip) || (allow_loopback && ip.is_loopback())
+}
+
+/// Whether `url` may be fetched at all: http(s), and not an IP literal outside
+/// the public internet. Host names are checked once resolved, by `PublicResolver`.
+pub fn check_url(url: &Url, allow_loopback: bool) -> Result<(), Blocked> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Blocked(format!("scheme '{}' is not allowed", url.scheme())));
+    }
+    let Some(host) = url.host_str() else {
+        return Err(Blocked("URL has no host".to_string()));
+    };
+    // IPv6 literals keep their brackets
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if is_allowed(ip, allow_loopback) {
+        Ok(())
+    } else {
+        Err(Blocked(format!("address {} is not public", ip)))
+    }
+}
+
+/// Resolves host names to their public addresses only. The connection goes
+/// to the addresses checked here, so a name cannot be re-pointed at a
+/// private address between the check and the fetch (DNS rebinding).
+pub struct PublicResolver {
+    pub allow_loopback: bool,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allow_loopback = self.allow_loopback;
+        Box::pin(async move {
+            let resolved: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let public: Vec<SocketAddr> =
+                resolved.into_iter().filter(|addr| is_allowed(addr.ip(), allow_loopback)).collect();
+            if public.is_empty() {
+                return Err(Box::new(Blocked(format!("{} has no public address", name.as_str()))) as _);
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_pass() {
+        for ip in ["93.184.215.14", "8.8.8.8", "2606:4700::1111", "2a00:1450:4001::200e"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "255.255.255.255", "224.0.0.1", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe", "::7f00:1", "2002:7f00:1::1", "2001:db8::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+
+        let check = |url: &str| check_url(&Url::parse(url).unwrap(), false);
+        assert!(check("https://example.com/a").is_ok());
+        assert!(check("http://93.184.215.14/").is_ok());
+        // Every spelling of an IP literal is normalized by the URL parser
+        for url in ["http://127.0.0.1/", "http://0x7f.1/", "http://2130706433/", "http://[::ffff:10.0.0.1]/", "ftp://example.com/"] {
+            assert!(check(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Just enough HTML to read the `<title>` and `<meta>` tags of a page head.
+
+const MAX_TITLE_CHARS: usize = 300;
+const MAX_DESCRIPTION_CHARS: usize = 500;
+
+/// What a page says about itself, before URLs are resolved.
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+#[derive(Default)]
+struct Tags {
+    title: Option<String>,
+    meta: Vec<(String, String)>,
+}
+
+impl Tags {
+    /// Content of the first `<meta>` named (or with the property) `key`.
+    fn meta(&self, key: &str) -> Option<String> {
+        self.meta.iter().find(|(name, _)| name == key).map(|(_, content)| content.clone())
+    }
+}
+
+/// OpenGraph first, then Twitter cards, then plain `<title>` and description.
+pub fn metadata(html: &str) -> Metadata {
+    let tags = scan(html);
+    let first = |keys: &[&str]| keys.iter().find_map(|key| tags.meta(key)).filter(|value| !value.is_empty());
+    Metadata {
+        title: first(&["og:title", "twitter:title"])
+            .or_else(|| tags.title.clone())
+            .map(|title| truncate(&title, MAX_TITLE_CHARS)),
+        description: first(&["og:description", "twitter:description", "description"])
+            .map(|description| truncate(&description, MAX_DESCRIPTION_CHARS)),
+        image: first(&["og:image:secure_url", "og:image", "og:image:url", "twitter:image", "twitter:image:src"]),
+        site_name: first(&["og:site_name"]).map(|site_name| truncate(&site_name, MAX_TITLE_CHARS)),
+    }
+}
+
+fn scan(html: &str) -> Tags {
+    let mut tags = Tags::default();
+    let lower = html.to_ascii_lowercase();
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find('<').map(|i| pos + i) {
+        let rest = &lower[start..];
+        if rest.starts_with("<!--") {
+            pos = rest.find("-->").map(|i| start + i + 3).unwrap_or(lower.len());
+            continue;
+        }
+        let name_len = rest[1..].find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len() - 1);
+        let name = &rest[1..1 + name_len];
+        let Some(end) = tag_end(&html[start..]).map(|i| start + i) else {
+            break;
+        };
+        match name {
+            "meta" => {
+                let attributes = attributes(&html[start + 1 + name_len..end]);
+                let get = |key: &str| attributes.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+                if let (Some(key), Some(content)) = (get("property").or(get("name")), get("content")) {
+                    tags.meta.push((key.to_ascii_lowercase(), clean(content)));
+                }
+            }
+            "title" if tags.title.is_none() => {
+                let close = lower[end..].find("</title").map(|i| end + i).unwrap_or(lower.len());
+                tags.title = Some(clean(&unescape(&html[end + 1..close]))).filter(|title| !title.is_empty());
+            }
+            // Everything the metadata could be in has been seen
+            "body" => break,
+            // Their contents are not markup, a "<meta" in a script is just text
+            "script" | "style" => {
+                let close = format!("</{}", name);
+                pos = lower[end..].find(&close).map(|i| end + i).unwrap_or(lower.len());
+                continue;
+            }
+            _ => {}
+        }
+        pos = end + 1;
+    }
+    tags
+}
+
+/// Offset of the `>` closing the tag at the start of `html`, skipping quoted values.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `name="value"` pairs, names lowercased, values unescaped.
+fn attributes(html: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = html.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+    while !rest.is_empty() {
+        let name_len = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '/').unwrap_or(rest.len());
+        let name = rest[..name_len].to_ascii_lowercase();
+        rest = rest[name_len..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    let close = after[1..].find(q).map(|i| i + 1).unwrap_or(after.len());
+                    (&after[1..close], after.get(close + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = unescape(raw);
+            rest = remaining;
+        }
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+    }
+    attributes
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&semi| semi <= 10).and_then(|semi| {
+            let entity = &rest[1..semi];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, semi))
+        });
+        match decoded {
+            Some((c, semi)) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Whitespace collapsed and without control characters.
+fn clean(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").chars().filter(|c| !c.is_control()).collect()
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_opengraph_and_falls_back_to_the_title() {
+        let html = r#"<!DOCTYPE html><html><head>
+            <!-- <meta property="og:title" content="commented out"> -->
+            <TITLE> Plain
+              title </TITLE>
+            <script>document.write('<meta property="og:description" content="scripted">')</script>
+            <meta name="description" content="Fish &amp; chips &amp;lt; &#x2014; &#8220;fresh&#8221;">
+            <meta content='Rust &lt;3' property=og:title />
+            <meta property="og:image" content="/img/a.png?x=1&amp;y=2">
+            </head><body><meta property="og:site_name" content="too late"></body></html>"#;
+        assert_eq!(
+            metadata(html),
+            Metadata {
+                title: Some("Rust <3".to_string()),
+                description: Some("Fish & chips &lt; — “fresh”".to_string()),
+                image: Some("/img/a.png?x=1&y=2".to_string()),
+                site_name: None,
+            }
+        );
+
+        let plain = metadata("<title>Only &amp; title</title><meta name=description content=''>");
+        assert_eq!(plain.title.as_deref(), Some("Only & title"));
+        assert_eq!(plain.description, None);
+        assert_eq!(metadata("<title>unclosed").title.as_deref(), Some("unclosed"));
+        assert_eq!(metadata("<meta property=\"og:title").title, None);
+        assert_eq!(truncate("abcdef", 3), "abc…");
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::sync::Arc;
+
+use actix::Addr;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::api::redis::{GetCommand, RedisActor, SetCommand};
+use crate::bus::{BusMessage, MessageBus};
+use crate::library::metrics::METRICS;
+use crate::settings::LinkPreviewSettings;
+
+pub mod guard;
+pub mod html;
+
+/// Name of the SSE event carrying the previews of a message.
+pub const ENRICHED_EVENT: &str = "message_enriched";
+
+/// What a link in a message points to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkPreview {
+    /// The URL as written in the message.
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute http(s) URL of an image, for images this is `url` itself.
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum PreviewError {
+    /// The URL or an address it resolves or redirects to is not allowed.
+    Blocked(String),
+    Fetch(String),
+}
+
+impl fmt::Display for PreviewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreviewError::Blocked(e) => write!(f, "link preview blocked: {}", e),
+            PreviewError::Fetch(e) => write!(f, "link preview fetch failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PreviewError {}
+
+impl From<reqwest::Error> for PreviewError {
+    fn from(e: reqwest::Error) -> Self {
+        // The resolver and the redirect policy report blocked addresses as errors
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&e);
+        while let Some(error) = source {
+            if let Some(blocked) = error.downcast_ref::<guard::Blocked>() {
+                return PreviewError::Blocked(blocked.to_string());
+            }
+            source = error.source();
+        }
+        PreviewError::Fetch(e.to_string())
+    }
+}
+
+/// The http(s) URLs in `msg`, in order, without duplicates, at most `max`.
+pub fn extract_urls(msg: &str, max: usize) -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+    for word in msg.split_whitespace() {
+        if urls.len() == max {
+            break;
+        }
+        let Some(start) = word.find("https://").or_else(|| word.find("http://")) else {
+            continue;
+        };
+        // Sentence punctuation and closing brackets around the link are not part of it
+        let mut candidate = word[start..].trim_end_matches(['.', ',', ';', ':', '!', '?', '"', '\'', '>', ']', '}']);
+        while candidate.ends_with(')') && candidate.matches('(').count() < candidate.matches(')').count() {
+            candidate = &candidate[..candidate.len() - 1];
+        }
+        if let Ok(url) = Url::parse(candidate) {
+            if url.host_str().is_some() && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    urls
+}
+
+/// Fetches pages and reads their metadata, refusing anything that is not
+/// on the public internet. Only the head of a page (`max_bytes`) is read.
+pub struct Fetcher {
+    client: reqwest::Client,
+    max_bytes: usize,
+    allow_loopback: bool,
+}
+
+impl Fetcher {
+    pub fn new(settings: &LinkPreviewSettings) -> Result<Self, PreviewError> {
+        Self::build(settings, false)
+    }
+
+    fn build(settings: &LinkPreviewSettings, allow_loopback: bool) -> Result<Self, PreviewError> {
+        let max_redirects = settings.max_redirects;
+        let redirect = Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(format!("more than {} redirects", max_redirects));
+            }
+            // Names are checked by the resolver, IP literals never reach it
+            match guard::check_url(attempt.url(), allow_loopback) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        });
+        let client = reqwest::Client::builder()
+            .timeout(settings.timeout)
+            .connect_timeout(settings.timeout)
+            .redirect(redirect)
+            .user_agent(settings.user_agent.as_str())
+            // A proxy would resolve the names itself, past the resolver
+            .no_proxy()
+            .dns_resolver(Arc::new(guard::PublicResolver { allow_loopback }))
+            .build()
+            .map_err(|e| PreviewError::Fetch(e.to_string()))?;
+        Ok(Fetcher { client, max_bytes: settings.max_bytes, allow_loopback })
+    }
+
+    /// The preview of `url`, `None` if the page has nothing to show.
+    pub async fn fetch(&self, url: &Url) -> Result<Option<LinkPreview>, PreviewError> {
+        guard::check_url(url, self.allow_loopback).map_err(|e| PreviewError::Blocked(e.0))?;
+        let mut response = self
+            .client
+            .get(url.clone())
+            .header(ACCEPT, "text/html,application/xhtml+xml;q=0.9,image/*;q=0.8")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(PreviewError::Fetch(format!("status {}", response.status())));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let preview = |title, description, image, site_name| LinkPreview {
+            url: url.to_string(),
+            title,
+            description,
+            image,
+            site_name,
+        };
+        if content_type.starts_with("image/") {
+            return Ok(Some(preview(None, None, Some(response.url().to_string()), None)));
+        }
+        if !(content_type.starts_with("text/html") || content_type.starts_with("application/xhtml+xml")) {
+            return Ok(None);
+        }
+        // Relative image URLs are relative to the page after redirects
+        let base = response.url().clone();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() >= self.max_bytes {
+                body.truncate(self.max_bytes);
+                break;
+            }
+        }
+        let metadata = html::metadata(&String::from_utf8_lossy(&body));
+        let image = metadata
+            .image
+            .and_then(|image| base.join(&image).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(String::from);
+        if metadata.title.is_none() && metadata.description.is_none() && image.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(preview(metadata.title, metadata.description, image, metadata.site_name)))
+    }
+}
+
+/// Link previews of chat messages, cached in Redis so a link posted in many
+/// rooms (or by many pods) is fetched once per `cache_ttl_secs`.
+pub struct LinkPreviewer {
+    fetcher: Fetcher,
+    redis: Addr<RedisActor>,
+    settings: LinkPreviewSettings,
+    /// One per message being enriched, shared by every worker.
+    permits: Arc<Semaphore>,
+}
+
+impl LinkPreviewer {
+    pub fn new(settings: &LinkPreviewSettings, redis: Addr<RedisActor>) -> Result<Self, PreviewError> {
+        Ok(LinkPreviewer {
+            fetcher: Fetcher::new(settings)?,
+            redis,
+            settings: settings.clone(),
+            permits: Arc::new(Semaphore::new(settings.max_concurrent)),
+        })
+    }
+
+    /// Room to enrich one more message, held until its `enrich` is done.
+    /// `None` while `max_concurrent` messages are being enriched: the
+    /// previews of `urls` are skipped rather than queued behind them.
+    pub fn permit(&self, urls: &[Url]) -> Option<OwnedSemaphorePermit> {
+        let permit = self.permits.clone().try_acquire_owned().ok();
+        if permit.is_none() {
+            METRICS.link_previews.with_label_values(&["skipped"]).inc_by(urls.len() as u64);
+        }
+        permit
+    }
+
+    /// The URLs of `msg` that get a preview, none when previews are disabled.
+    pub fn urls(&self, msg: &str) -> Vec<Url> {
+        if !self.settings.enabled {
+            return Vec::new();
+        }
+        extract_urls(msg, self.settings.max_urls)
+    }
+
+    fn cache_key(url: &Url) -> String {
+        format!("chat:link-preview:{}", hex::encode(Sha256::digest(url.as_str().as_bytes())))
+    }
+
+    /// The preview of `url` from the cache, or fetched and cached. Failures
+    /// are cached too (for `failure_ttl_secs`), so a dead link is not
+    /// fetched again for every message it appears in.
+    pub async fn preview(&self, url: &Url) -> Option<LinkPreview> {
+        let key = Self::cache_key(url);
+        match self.redis.send(GetCommand { key: key.clone() }).await {
+            Ok(Ok(Some(cached))) => match serde_json::from_str::<Option<LinkPreview>>(&cached) {
+                Ok(preview) => {
+                    METRICS.link_previews.with_label_values(&["cached"]).inc();
+                    return preview;
+                }
+                Err(e) => tracing::warn!(error = %e, "Ignoring unreadable cached link preview"),
+            },
+            Ok(Ok(None)) => {}
+            // Fetching without the cache still works, just more often
+            Ok(Err(e)) => tracing::warn!(error = %e, "Link preview cache lookup failed"),
+            Err(e) => tracing::warn!(error = %e, "Link preview cache lookup failed"),
+        }
+
+        let (preview, outcome, ttl) = match self.fetcher.fetch(url).await {
+            Ok(Some(preview)) => (Some(preview), "fetched", self.settings.cache_ttl_secs),
+            Ok(None) => (None, "empty", self.settings.failure_ttl_secs),
+            Err(e) => {
+                tracing::info!(url = %url, error = %e, "No link preview");
+                let outcome = if matches!(e, PreviewError::Blocked(_)) { "blocked" } else { "failed" };
+                (None, outcome, self.settings.failure_ttl_secs)
+            }
+        };
+        METRICS.link_previews.with_label_values(&[outcome]).inc();
+        let value = serde_json::to_string(&preview).unwrap_or_default();
+        match self.redis.send(SetCommand { key, value, ex: Some(ttl) }).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::warn!(error = %e, "Failed to cache link preview"),
+            Err(e) => tracing::warn!(error = %e, "Failed to cache link preview"),
+        }
+        preview
+    }
+
+    /// Looks up the previews of `urls` and publishes them to the room as a
+    /// `message_enriched` event. Runs after the message itself was published,
+    /// so a slow site never delays the message.
+    pub async fn enrich(
+        &self,
+        bus: &dyn MessageBus,
+        room_id: i32,
+        message_id: String,
+        request_id: Option<String>,
+        urls: Vec<Url>,
+    ) {
+        let lookups = urls.iter().map(|url| self.preview(url));
+        let previews: Vec<LinkPreview> = futures::future::join_all(lookups).await.into_iter().flatten().collect();
+        if previews.is_empty() {
+            return;
+        }
+        let data = serde_json::json!({ "message_id": message_id, "room_id": room_id, "previews": previews });
+        let mut msg = BusMessage::event_for_room(room_id, ENRICHED_EVENT, data.to_string().into_bytes());
+        // A retried POST enriches again, subscribers drop the second event like the second message
+        msg.attributes.insert("message_id".to_string(), format!("{}:enriched", message_id));
+        if let Some(request_id) = request_id {
+            msg.attributes.insert("request_id".to_string(), request_id);
+        }
+        if let Err(e) = bus.publish(msg).await {
+            tracing::error!(error = %e, message_id, "Failed to publish link previews");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+
+    #[test]
+    fn finds_links_in_text() {
+        let urls = extract_urls(
+            "see https://example.com/a, (http://en.wikipedia.org/wiki/Rust_(language)) and <https://example.com/a> \
+             or ftp://example.com https://example.org/b?c=1. https://example.net",
+            3,
+        );
+        let urls: Vec<&str> = urls.iter().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            ["https://example.com/a", "http://en.wikipedia.org/wiki/Rust_(language)", "https://example.org/b?c=1"]
+        );
+        assert!(extract_urls("no links, just http:// and https://", 3).is_empty());
+    }
+
+    fn settings() -> LinkPreviewSettings {
+        LinkPreviewSettings {
+            enabled: true,
+            max_urls: 3,
+            max_concurrent: 4,
+            timeout: Duration::from_secs(2),
+            max_bytes: 4096,
+            max_redirects: 2,
+            cache_ttl_secs: 60,
+            failure_ttl_secs: 60,
+            user_agent: "test".to_string(),
+        }
+    }
+
+    async fn page() -> HttpResponse {
+        // The metadata is in the head, the padding behind it is never read
+        let padding = "x".repeat(100_000);
+        HttpResponse::Ok().content_type("text/html; charset=utf-8").body(format!(
+            r#"<html><head><title>Page</title><meta property="og:image" content="/a.png"></head><body>{}</body></html>"#,
+            padding
+        ))
+    }
+
+    #[actix_web::test]
+    async fn fetches_public_pages_only() {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/page", web::get().to(page))
+                .route("/image", web::get().to(|| async { HttpResponse::Ok().content_type("image/png").body("png") }))
+                .route("/file", web::get().to(|| async { HttpResponse::Ok().content_type("application/zip").body("zip") }))
+                .route("/redirect", web::get().to(|| async {
+                    HttpResponse::Found().insert_header(("Location", "http://169.254.169.254/latest/meta-data/")).finish()
+                }))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let address = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let url = |path: &str| Url::parse(&format!("http://{}{}", address, path)).unwrap();
+
+        // The test server is on loopback, which only a test fetcher may reach, and nothing else private
+        let fetcher = Fetcher::build(&settings(), true).unwrap();
+        let preview = fetcher.fetch(&url("/page")).await.unwrap().unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Page"));
+        assert_eq!(preview.image, Some(url("/a.png").to_string()));
+        assert_eq!(fetcher.fetch(&url("/image")).await.unwrap().unwrap().image, Some(url("/image").to_string()));
+        assert!(fetcher.fetch(&url("/file")).await.unwrap().is_none());
+        assert!(matches!(fetcher.fetch(&url("/redirect")).await, Err(PreviewError::Blocked(_))));
+
+        let fetcher = Fetcher::new(&settings()).unwrap();
+        assert!(matches!(fetcher.fetch(&url("/page")).await, Err(PreviewError::Blocked(_))));
+        let by_name = Url::parse(&format!("http://localhost:{}/page", address.port())).unwrap();
+        assert!(matches!(fetcher.fetch(&by_name).await, Err(PreviewError::Blocked(_))));
+        handle.stop(false).await;
+    }
+}
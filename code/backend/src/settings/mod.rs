@@ -0,0 +1,748 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::PROJECT_PATH;
+
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    pub bind_address: String,
+    pub workers: usize,
+    pub json_limit_bytes: usize,
+    /// Time allowed for draining streams, the subscriber and pools on shutdown.
+    pub shutdown_timeout_secs: u64,
+    /// Wait between failing readiness and stopping, for load balancers to notice.
+    pub pre_stop_delay_secs: u64,
+    /// Proxies whose `X-Forwarded-For` is believed. Empty: the peer address is the client.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Clone)]
+pub struct AuthSettings {
+    /// HS256 key tokens are signed and verified with.
+    pub jwt_secret: String,
+}
+
+impl fmt::Debug for AuthSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthSettings").field("jwt_secret", &"<redacted>").finish()
+    }
+}
+
+/// Upper bound of `link_preview.max_urls`, every URL may cost a fetch.
+pub const MAX_PREVIEW_URLS: usize = 10;
+
+/// Shorter keys can be brute forced from a single token.
+pub const MIN_JWT_SECRET_BYTES: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct DatabaseSettings {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub name: String,
+    pub max_connections: u32,
+    /// Apply pending migrations before the server starts.
+    pub migrate_on_startup: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RedisSettings {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusKind {
+    Memory,
+    Redis,
+    RedisStreams,
+    PubSub,
+}
+
+impl FromStr for BusKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(BusKind::Memory),
+            "redis" => Ok(BusKind::Redis),
+            "redis-streams" => Ok(BusKind::RedisStreams),
+            "pubsub" => Ok(BusKind::PubSub),
+            _ => Err("expected one of memory, redis, redis-streams, pubsub".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RedisStreamSettings {
+    pub prefix: String,
+    /// Consumer group, required for `redis-streams`. Kept across restarts,
+    /// pods that share one split the messages between them.
+    pub group: Option<String>,
+    /// Idle time after which entries left pending by another consumer are claimed.
+    pub claim_idle_ms: usize,
+    pub maxlen: usize,
+    pub start_id: String,
+    pub block_ms: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct BusSettings {
+    pub kind: BusKind,
+    /// Pub/Sub topic, also the Redis Pub/Sub channel.
+    pub topic: String,
+    /// Pub/Sub subscription, required for `pubsub`.
+    pub subscription: Option<String>,
+    pub stream: RedisStreamSettings,
+    pub dead_letter_key: String,
+    pub dead_letter_max_len: usize,
+    /// Deliveries a message may fail (and be nacked) before it is dead-lettered.
+    pub max_delivery_attempts: u32,
+    pub dedup_ttl_secs: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SseSettings {
+    /// Slots in the broadcast channel of a room before a client lags.
+    pub capacity: usize,
+    /// Messages kept per room to backfill lagging clients.
+    pub history_size: usize,
+    /// Interval of the `: ping` comments keeping idle connections open.
+    pub heartbeat: Duration,
+    /// Open streams allowed per user, 0 for no limit.
+    pub max_connections_per_user: usize,
+    /// Reconnection delay suggested to clients through `retry:`.
+    pub retry_ms: u64,
+    /// How long a room nobody listens to keeps its channel and history.
+    pub room_idle_ttl: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsSettings {
+    /// Exact origins, or `*` for any origin (not allowed with credentials).
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub supports_credentials: bool,
+    /// Seconds browsers may cache a preflight response.
+    pub max_age: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err("expected one of json, pretty".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Rotation::Never),
+            "hourly" => Ok(Rotation::Hourly),
+            "daily" => Ok(Rotation::Daily),
+            _ => Err("expected one of never, hourly, daily".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageKind {
+    Local,
+    S3,
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(StorageKind::Local),
+            "s3" => Ok(StorageKind::S3),
+            _ => Err("expected one of local, s3".to_string()),
+        }
+    }
+}
+
+/// An S3 compatible bucket (AWS, MinIO, ...).
+#[derive(Debug, Clone)]
+pub struct S3Settings {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// `endpoint/bucket/key` instead of `bucket.endpoint/key`, what MinIO expects.
+    pub path_style: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttachmentSettings {
+    pub storage: StorageKind,
+    /// Root of the `local` storage.
+    pub local_dir: PathBuf,
+    /// Required for `s3`.
+    pub s3: Option<S3Settings>,
+    pub max_bytes: usize,
+    /// MIME types accepted, as detected from the content.
+    pub allowed_types: Vec<String>,
+    /// Longest side of image thumbnails, 0 disables them.
+    pub thumbnail_px: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkPreviewSettings {
+    pub enabled: bool,
+    /// URLs previewed per message, the first ones win.
+    pub max_urls: usize,
+    /// Messages whose previews are fetched at once, further ones get none.
+    pub max_concurrent: usize,
+    /// Deadline of one fetch, redirects included.
+    pub timeout: Duration,
+    /// Bytes of a page read before giving up on finding its metadata.
+    pub max_bytes: usize,
+    pub max_redirects: usize,
+    /// How long a fetched preview is cached in Redis.
+    pub cache_ttl_secs: usize,
+    /// How long a URL without a preview is not fetched again.
+    pub failure_ttl_secs: usize,
+    pub user_agent: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogFileSettings {
+    pub dir: PathBuf,
+    pub file_name: String,
+    /// Size that triggers a rotation, 0 for no limit.
+    pub max_bytes: u64,
+    pub rotation: Rotation,
+    /// Rotated files kept next to the current one.
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    /// `tracing_subscriber::EnvFilter` directives, e.g. `info,sqlx=warn`.
+    pub level: String,
+    pub format: LogFormat,
+    /// Lines buffered per output before new ones are dropped.
+    pub queue_size: usize,
+    /// Written in addition to stdout, `None` when `log.dir` is empty.
+    pub file: Option<LogFileSettings>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Name of this pod, used to scope per-pod state in Redis.
+    pub pod_name: String,
+    pub server: ServerSettings,
+    pub auth: AuthSettings,
+    pub database: DatabaseSettings,
+    pub redis: RedisSettings,
+    pub bus: BusSettings,
+    pub sse: SseSettings,
+    pub cors: CorsSettings,
+    pub log: LogSettings,
+    pub attachments: AttachmentSettings,
+    pub link_preview: LinkPreviewSettings,
+}
+
+/// Every missing or invalid key found while loading.
+#[derive(Debug)]
+pub struct SettingsError(pub Vec<String>);
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid settings:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+/// Looks keys up in the environment first, then in the settings file,
+/// and collects errors instead of stopping at the first one.
+struct Loader {
+    file: toml::Table,
+    /// The environment variables, read once.
+    env: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    fn raw(&self, path: &str, env: &str) -> Option<String> {
+        if let Some(value) = self.env.get(env) {
+            return Some(value.clone());
+        }
+        let mut value = self.file.get(path.split('.').next()?)?;
+        for part in path.split('.').skip(1) {
+            value = value.get(part)?;
+        }
+        match value {
+            toml::Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, path: &str, env: &str, raw: String) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        match raw.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{} ({}): invalid value '{}': {}", path, env, raw, e));
+                None
+            }
+        }
+    }
+
+    fn required<T: FromStr + Default>(&mut self, path: &str, env: &str) -> T
+    where
+        T::Err: fmt::Display,
+    {
+        match self.raw(path, env) {
+            Some(raw) => self.parse(path, env, raw).unwrap_or_default(),
+            None => {
+                self.errors.push(format!("{} ({}): missing", path, env));
+                T::default()
+            }
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, path: &str, env: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let raw = self.raw(path, env)?;
+        self.parse(path, env, raw)
+    }
+
+    fn or<T: FromStr>(&mut self, path: &str, env: &str, default: T) -> T
+    where
+        T::Err: fmt::Display,
+    {
+        self.optional(path, env).unwrap_or(default)
+    }
+
+    /// A TOML array in the file, or a comma separated environment variable.
+    fn list(&mut self, path: &str, env: &str, default: &[&str]) -> Vec<String> {
+        if let Some(value) = self.env.get(env) {
+            return value.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+        }
+        let mut value = self.file.get(path.split('.').next().unwrap_or_default());
+        for part in path.split('.').skip(1) {
+            value = value.and_then(|v| v.get(part));
+        }
+        match value {
+            None => default.iter().map(|v| v.to_string()).collect(),
+            Some(toml::Value::Array(items)) => items
+                .iter()
+                .filter_map(|item| match item.as_str() {
+                    Some(item) => Some(item.to_string()),
+                    None => {
+                        self.errors.push(format!("{} ({}): invalid item {}, expected a string", path, env, item));
+                        None
+                    }
+                })
+                .collect(),
+            Some(other) => {
+                self.errors.push(format!("{} ({}): invalid value {}, expected an array", path, env, other));
+                Vec::new()
+            }
+        }
+    }
+
+    fn check(&mut self, ok: bool, message: &str) {
+        if !ok {
+            self.errors.push(message.to_string());
+        }
+    }
+}
+
+impl Settings {
+    /// Loads `settings.toml` (or the file in `SETTINGS_FILE`) and applies
+    /// environment overrides. The file is optional, the environment alone is enough.
+    pub fn load() -> Result<Settings, SettingsError> {
+        Self::from_loader(Self::loader()?)
+    }
+
+    /// Only the `[database]` section, for commands that need nothing else.
+    pub fn load_database() -> Result<DatabaseSettings, SettingsError> {
+        let mut l = Self::loader()?;
+        let database = Self::database(&mut l);
+        if !l.errors.is_empty() {
+            return Err(SettingsError(l.errors));
+        }
+        Ok(database)
+    }
+
+    fn loader() -> Result<Loader, SettingsError> {
+        let path = std::env::var("SETTINGS_FILE").unwrap_or_else(|_| format!("{}/settings.toml", PROJECT_PATH));
+        let file = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .parse::<toml::Table>()
+                .map_err(|e| SettingsError(vec![format!("{}: {}", path, e)]))?,
+            Err(_) if std::env::var("SETTINGS_FILE").is_err() => toml::Table::new(),
+            Err(e) => return Err(SettingsError(vec![format!("{}: {}", path, e)])),
+        };
+        // Non-Unicode variables cannot hold a setting anyway
+        let env = std::env::vars_os().filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)));
+        Ok(Loader { file, env: env.collect(), errors: Vec::new() })
+    }
+
+    fn database(l: &mut Loader) -> DatabaseSettings {
+        DatabaseSettings {
+            host: l.or("database.host", "DATABASE_HOST", "localhost".to_string()),
+            port: l.or("database.port", "DATABASE_PORT", 5432),
+            user: l.required("database.user", "DATABASE_USER"),
+            password: l.required("database.password", "DATABASE_PASSWORD"),
+            name: l.required("database.name", "DATABASE_NAME"),
+            max_connections: l.or("database.max_connections", "DATABASE_MAX_CONNECTIONS", 100),
+            migrate_on_startup: l.or("database.migrate_on_startup", "DATABASE_MIGRATE_ON_STARTUP", false),
+        }
+    }
+
+    fn from_loader(mut l: Loader) -> Result<Settings, SettingsError> {
+        let pod_name = l.or("pod_name", "HOSTNAME", "chat-sample".to_string());
+
+        let server = ServerSettings {
+            bind_address: l.or("server.bind_address", "SERVER_BIND_ADDRESS", "0.0.0.0:8080".to_string()),
+            workers: l.or("server.workers", "SERVER_WORKERS", 20),
+            json_limit_bytes: l.or("server.json_limit_bytes", "SERVER_JSON_LIMIT_BYTES", 64 * 1024),
+            shutdown_timeout_secs: l.or("server.shutdown_timeout_secs", "SERVER_SHUTDOWN_TIMEOUT_SECS", 30),
+            pre_stop_delay_secs: l.or("server.pre_stop_delay_secs", "SERVER_PRE_STOP_DELAY_SECS", 5),
+            trusted_proxies: l
+                .list("server.trusted_proxies", "SERVER_TRUSTED_PROXIES", &[])
+                .into_iter()
+                .filter_map(|proxy| l.parse("server.trusted_proxies", "SERVER_TRUSTED_PROXIES", proxy))
+                .collect(),
+        };
+        l.check(server.workers > 0, "server.workers (SERVER_WORKERS): must be greater than 0");
+        l.check(server.json_limit_bytes > 0, "server.json_limit_bytes (SERVER_JSON_LIMIT_BYTES): must be greater than 0");
+
+        let errors = l.errors.len();
+        let auth = AuthSettings {
+            jwt_secret: l.required("auth.jwt_secret", "JWT_SECRET"),
+        };
+        // Reported once, as missing or as too short
+        l.check(
+            l.errors.len() > errors || auth.jwt_secret.len() >= MIN_JWT_SECRET_BYTES,
+            &format!("auth.jwt_secret (JWT_SECRET): must be at least {} bytes", MIN_JWT_SECRET_BYTES),
+        );
+
+        let database = Self::database(&mut l);
+
+        let redis = RedisSettings {
+            url: l.required("redis.url", "REDIS_URL"),
+        };
+
+        let bus = BusSettings {
+            kind: l.or("bus.kind", "MESSAGE_BUS", BusKind::Memory),
+            topic: l.or("bus.topic", "TOPIC_NAME", "chat-messages".to_string()),
+            subscription: l.optional("bus.subscription", "SUBSCRIBE_NAME"),
+            stream: RedisStreamSettings {
+                prefix: l.or("bus.stream.prefix", "REDIS_STREAM_PREFIX", "{chat}".to_string()),
+                group: l.optional("bus.stream.group", "REDIS_STREAM_GROUP"),
+                claim_idle_ms: l.or("bus.stream.claim_idle_ms", "REDIS_STREAM_CLAIM_IDLE_MS", 60000),
+                maxlen: l.or("bus.stream.maxlen", "REDIS_STREAM_MAXLEN", 10000),
+                // "$" only delivers new entries, "0" replays the whole stream, or any entry id
+                start_id: l.or("bus.stream.start_id", "REDIS_STREAM_START_ID", "$".to_string()),
+                block_ms: l.or("bus.stream.block_ms", "REDIS_STREAM_BLOCK_MS", 5000),
+            },
+            dead_letter_key: l.or("bus.dead_letter_key", "DEAD_LETTER_KEY", "chat:dead-letter".to_string()),
+            dead_letter_max_len: l.or("bus.dead_letter_max_len", "DEAD_LETTER_MAX_LEN", 1000),
+            max_delivery_attempts: l.or("bus.max_delivery_attempts", "BUS_MAX_DELIVERY_ATTEMPTS", 5),
+            dedup_ttl_secs: l.or("bus.dedup_ttl_secs", "DEDUP_TTL_SECS", 600),
+        };
+        l.check(
+            bus.kind != BusKind::PubSub || bus.subscription.is_some(),
+            "bus.subscription (SUBSCRIBE_NAME): missing, required when bus.kind is pubsub",
+        );
+        l.check(
+            bus.max_delivery_attempts > 0,
+            "bus.max_delivery_attempts (BUS_MAX_DELIVERY_ATTEMPTS): must be greater than 0",
+        );
+        l.check(
+            bus.kind != BusKind::RedisStreams || bus.stream.group.as_deref().is_some_and(|group| !group.is_empty()),
+            "bus.stream.group (REDIS_STREAM_GROUP): missing, required when bus.kind is redis-streams",
+        );
+        l.check(bus.stream.claim_idle_ms > 0, "bus.stream.claim_idle_ms (REDIS_STREAM_CLAIM_IDLE_MS): must be greater than 0");
+
+        let sse = SseSettings {
+            capacity: l.or("sse.capacity", "SSE_CAPACITY", 2000),
+            history_size: l.or("sse.history_size", "SSE_HISTORY_SIZE", 10000),
+            heartbeat: Duration::from_secs(l.or("sse.heartbeat_secs", "SSE_HEARTBEAT_SECS", 15)),
+            max_connections_per_user: l.or("sse.max_connections_per_user", "SSE_MAX_CONNECTIONS_PER_USER", 5),
+            retry_ms: l.or("sse.retry_ms", "SSE_RETRY_MS", 3000),
+            room_idle_ttl: Duration::from_secs(l.or("sse.room_idle_ttl_secs", "SSE_ROOM_IDLE_TTL_SECS", 300)),
+        };
+        l.check(sse.capacity > 0, "sse.capacity (SSE_CAPACITY): must be greater than 0");
+        l.check(!sse.heartbeat.is_zero(), "sse.heartbeat_secs (SSE_HEARTBEAT_SECS): must be greater than 0");
+        l.check(
+            !sse.room_idle_ttl.is_zero(),
+            "sse.room_idle_ttl_secs (SSE_ROOM_IDLE_TTL_SECS): must be greater than 0",
+        );
+
+        let cors = CorsSettings {
+            allowed_origins: l.list("cors.allowed_origins", "CORS_ALLOWED_ORIGINS", &["http://localhost"]),
+            allowed_methods: l.list("cors.allowed_methods", "CORS_ALLOWED_METHODS", &["GET", "POST", "PATCH", "DELETE", "OPTIONS"]),
+            allowed_headers: l.list(
+                "cors.allowed_headers",
+                "CORS_ALLOWED_HEADERS",
+                &["Authorization", "Content-Type", "Accept", "Idempotency-Key", "X-Request-Id"],
+            ),
+            supports_credentials: l.or("cors.supports_credentials", "CORS_SUPPORTS_CREDENTIALS", true),
+            max_age: l.or("cors.max_age", "CORS_MAX_AGE", 60 * 60 * 24),
+        };
+        l.check(
+            !(cors.supports_credentials && cors.allowed_origins.iter().any(|o| o == "*")),
+            "cors.allowed_origins (CORS_ALLOWED_ORIGINS): '*' cannot be used with cors.supports_credentials",
+        );
+        for method in &cors.allowed_methods {
+            l.check(
+                actix_web::http::Method::from_bytes(method.as_bytes()).is_ok(),
+                &format!("cors.allowed_methods (CORS_ALLOWED_METHODS): invalid method '{}'", method),
+            );
+        }
+        for header in &cors.allowed_headers {
+            l.check(
+                actix_web::http::header::HeaderName::from_bytes(header.as_bytes()).is_ok(),
+                &format!("cors.allowed_headers (CORS_ALLOWED_HEADERS): invalid header '{}'", header),
+            );
+        }
+
+        let log = LogSettings {
+            level: l.or("log.level", "RUST_LOG", "info".to_string()),
+            format: l.or("log.format", "LOG_FORMAT", LogFormat::Json),
+            queue_size: l.or("log.queue_size", "LOG_QUEUE_SIZE", 10000),
+            file: Some(l.or("log.dir", "LOG_DIR", format!("{}/log", PROJECT_PATH)))
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| LogFileSettings {
+                    dir: PathBuf::from(dir),
+                    file_name: l.or("log.file_name", "LOG_FILE_NAME", "actix.log".to_string()),
+                    max_bytes: l.or("log.max_file_bytes", "LOG_MAX_FILE_BYTES", 10 * 1024 * 1024),
+                    rotation: l.or("log.rotation", "LOG_ROTATION", Rotation::Daily),
+                    max_files: l.or("log.max_files", "LOG_MAX_FILES", 7),
+                }),
+        };
+        l.check(log.queue_size > 0, "log.queue_size (LOG_QUEUE_SIZE): must be greater than 0");
+        l.check(
+            tracing_subscriber::EnvFilter::try_new(&log.level).is_ok(),
+            &format!("log.level (RUST_LOG): invalid filter '{}'", log.level),
+        );
+
+        let storage = l.or("attachments.storage", "ATTACHMENT_STORAGE", StorageKind::Local);
+        let attachments = AttachmentSettings {
+            local_dir: PathBuf::from(l.or("attachments.local_dir", "ATTACHMENT_DIR", format!("{}/uploads", PROJECT_PATH))),
+            // Only required (and reported missing) when s3 is selected
+            s3: (storage == StorageKind::S3).then(|| S3Settings {
+                endpoint: l.required("attachments.s3.endpoint", "S3_ENDPOINT"),
+                region: l.or("attachments.s3.region", "S3_REGION", "us-east-1".to_string()),
+                bucket: l.required("attachments.s3.bucket", "S3_BUCKET"),
+                access_key_id: l.required("attachments.s3.access_key_id", "S3_ACCESS_KEY_ID"),
+                secret_access_key: l.required("attachments.s3.secret_access_key", "S3_SECRET_ACCESS_KEY"),
+                path_style: l.or("attachments.s3.path_style", "S3_PATH_STYLE", true),
+            }),
+            storage,
+            max_bytes: l.or("attachments.max_bytes", "ATTACHMENT_MAX_BYTES", 10 * 1024 * 1024),
+            allowed_types: l.list(
+                "attachments.allowed_types",
+                "ATTACHMENT_ALLOWED_TYPES",
+                &["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "text/plain"],
+            ),
+            thumbnail_px: l.or("attachments.thumbnail_px", "ATTACHMENT_THUMBNAIL_PX", 256),
+        };
+        l.check(attachments.max_bytes > 0, "attachments.max_bytes (ATTACHMENT_MAX_BYTES): must be greater than 0");
+        if let Some(s3) = &attachments.s3 {
+            l.check(
+                reqwest::Url::parse(&s3.endpoint).is_ok_and(|url| matches!(url.scheme(), "http" | "https")),
+                &format!("attachments.s3.endpoint (S3_ENDPOINT): invalid URL '{}'", s3.endpoint),
+            );
+        }
+
+        let link_preview = LinkPreviewSettings {
+            enabled: l.or("link_preview.enabled", "LINK_PREVIEW_ENABLED", true),
+            max_urls: l.or("link_preview.max_urls", "LINK_PREVIEW_MAX_URLS", 3),
+            max_concurrent: l.or("link_preview.max_concurrent", "LINK_PREVIEW_MAX_CONCURRENT", 16),
+            timeout: Duration::from_millis(l.or("link_preview.timeout_ms", "LINK_PREVIEW_TIMEOUT_MS", 5000)),
+            max_bytes: l.or("link_preview.max_bytes", "LINK_PREVIEW_MAX_BYTES", 512 * 1024),
+            max_redirects: l.or("link_preview.max_redirects", "LINK_PREVIEW_MAX_REDIRECTS", 3),
+            cache_ttl_secs: l.or("link_preview.cache_ttl_secs", "LINK_PREVIEW_CACHE_TTL_SECS", 24 * 60 * 60),
+            failure_ttl_secs: l.or("link_preview.failure_ttl_secs", "LINK_PREVIEW_FAILURE_TTL_SECS", 10 * 60),
+            user_agent: l.or("link_preview.user_agent", "LINK_PREVIEW_USER_AGENT", "chat-sample-link-preview/1.0".to_string()),
+        };
+        l.check(
+            link_preview.max_urls <= MAX_PREVIEW_URLS,
+            &format!("link_preview.max_urls (LINK_PREVIEW_MAX_URLS): must be at most {}", MAX_PREVIEW_URLS),
+        );
+        l.check(
+            link_preview.max_concurrent > 0,
+            "link_preview.max_concurrent (LINK_PREVIEW_MAX_CONCURRENT): must be greater than 0",
+        );
+        l.check(!link_preview.timeout.is_zero(), "link_preview.timeout_ms (LINK_PREVIEW_TIMEOUT_MS): must be greater than 0");
+        l.check(link_preview.max_bytes > 0, "link_preview.max_bytes (LINK_PREVIEW_MAX_BYTES): must be greater than 0");
+        l.check(
+            link_preview.cache_ttl_secs > 0 && link_preview.failure_ttl_secs > 0,
+            "link_preview.cache_ttl_secs and link_preview.failure_ttl_secs: must be greater than 0",
+        );
+
+        if !l.errors.is_empty() {
+            return Err(SettingsError(l.errors));
+        }
+        Ok(Settings { pod_name, server, auth, database, redis, bus, sse, cors, log, attachments, link_preview })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: &str = r#"
+        [auth]
+        jwt_secret = "0123456789abcdef0123456789abcdef"
+        [database]
+        user = "myuser"
+        password = "mypassword"
+        name = "chat"
+        [redis]
+        url = "redis://localhost:6379"
+    "#;
+
+    fn load_with_env(file: &str, env: &[(&str, &str)]) -> Result<Settings, SettingsError> {
+        let env = env.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        Settings::from_loader(Loader { file: file.parse().unwrap(), env, errors: Vec::new() })
+    }
+
+    fn load(file: &str) -> Result<Settings, SettingsError> {
+        load_with_env(file, &[])
+    }
+
+    #[test]
+    fn file_values_and_defaults() {
+        let file = format!(
+            "{}\n[server]\nworkers = 4\n[sse]\nheartbeat_secs = 30\n[cors]\nallowed_origins = [\"https://chat.example.com\"]",
+            REQUIRED
+        );
+        let settings = load(&file).unwrap();
+        assert_eq!(settings.server.workers, 4);
+        assert_eq!(settings.sse.heartbeat, Duration::from_secs(30));
+        assert_eq!(settings.cors.allowed_origins, ["https://chat.example.com"]);
+        assert_eq!(settings.database.user, "myuser");
+        assert_eq!(settings.redis.url, "redis://localhost:6379");
+        // Everything else has its default
+        assert_eq!(settings.pod_name, "chat-sample");
+        assert_eq!(settings.server.bind_address, "0.0.0.0:8080");
+        assert_eq!(settings.server.pre_stop_delay_secs, 5);
+        assert!(settings.server.trusted_proxies.is_empty());
+        assert_eq!(settings.database.port, 5432);
+        assert_eq!(settings.bus.kind, BusKind::Memory);
+        assert_eq!(settings.sse.history_size, 10000);
+        assert_eq!(settings.cors.allowed_methods, ["GET", "POST", "PATCH", "DELETE", "OPTIONS"]);
+        assert_eq!(settings.log.file.unwrap().dir, PathBuf::from(format!("{}/log", PROJECT_PATH)));
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file = format!("{}\n[server]\nworkers = 4\nbind_address = \"127.0.0.1:9000\"", REQUIRED);
+        let settings = load_with_env(
+            &file,
+            &[
+                ("SERVER_WORKERS", "8"),
+                ("DATABASE_PASSWORD", "from-env"),
+                ("CORS_ALLOWED_ORIGINS", "https://a.example.com, https://b.example.com,"),
+                ("HOSTNAME", "chat-7f9c-x2"),
+                ("SERVER_TRUSTED_PROXIES", "10.0.0.1, ::1"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(settings.server.workers, 8);
+        assert_eq!(settings.server.trusted_proxies, ["10.0.0.1".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()]);
+        assert_eq!(settings.database.password, "from-env");
+        assert_eq!(settings.cors.allowed_origins, ["https://a.example.com", "https://b.example.com"]);
+        assert_eq!(settings.pod_name, "chat-7f9c-x2");
+        // Keys without a variable set keep the file's value
+        assert_eq!(settings.server.bind_address, "127.0.0.1:9000");
+        assert_eq!(settings.database.user, "myuser");
+    }
+
+    #[test]
+    fn every_error_is_reported_at_once() {
+        let file = format!("{}\n[server]\nworkers = 0\n[database]", REQUIRED.replace("[database]", "[unused]"));
+        let errors = load_with_env(
+            &file,
+            &[("DATABASE_USER", "myuser"), ("DATABASE_PORT", "postgres"), ("MESSAGE_BUS", "kafka")],
+        )
+        .unwrap_err()
+        .0;
+        assert_eq!(
+            errors,
+            [
+                "server.workers (SERVER_WORKERS): must be greater than 0",
+                "database.port (DATABASE_PORT): invalid value 'postgres': invalid digit found in string",
+                "database.password (DATABASE_PASSWORD): missing",
+                "database.name (DATABASE_NAME): missing",
+                "bus.kind (MESSAGE_BUS): invalid value 'kafka': expected one of memory, redis, redis-streams, pubsub",
+            ]
+        );
+        let message = SettingsError(errors).to_string();
+        assert!(message.starts_with("invalid settings:\n  - server.workers"), "{}", message);
+    }
+
+    #[test]
+    fn required_values_are_reported_missing() {
+        let errors = load("").unwrap_err().0;
+        assert_eq!(
+            errors,
+            [
+                "auth.jwt_secret (JWT_SECRET): missing",
+                "database.user (DATABASE_USER): missing",
+                "database.password (DATABASE_PASSWORD): missing",
+                "database.name (DATABASE_NAME): missing",
+                "redis.url (REDIS_URL): missing",
+            ]
+        );
+        let short = load_with_env(&REQUIRED.replace("0123456789abcdef0123456789abcdef", "secret"), &[]).unwrap_err().0;
+        assert_eq!(short, ["auth.jwt_secret (JWT_SECRET): must be at least 32 bytes"]);
+    }
+
+    #[test]
+    fn redis_streams_need_a_configured_group() {
+        let streams = format!("pod_name = \"chat-7f9c-x2\"\n{}\n[bus]\nkind = \"redis-streams\"", REQUIRED);
+        let errors = load(&streams).unwrap_err().0;
+        assert_eq!(errors, ["bus.stream.group (REDIS_STREAM_GROUP): missing, required when bus.kind is redis-streams"]);
+
+        let settings = load(&format!("{}\n[bus.stream]\ngroup = \"chat-0\"", streams)).unwrap();
+        // The group does not follow the pod name, which changes on every restart
+        assert_eq!(settings.bus.stream.group.as_deref(), Some("chat-0"));
+        assert_eq!(settings.bus.stream.claim_idle_ms, 60000);
+    }
+}
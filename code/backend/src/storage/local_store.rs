@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use actix_web::web::Bytes;
+use futures::future::BoxFuture;
+
+use crate::storage::{validate_key, ObjectStore, StoreError};
+
+/// Objects as files under a directory, for development and single node setups.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: &Path) -> Self {
+        LocalStore { root: root.to_path_buf() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, StoreError> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+impl ObjectStore for LocalStore {
+    fn put(&self, key: &str, data: Bytes, _content_type: &str) -> BoxFuture<'_, Result<(), StoreError>> {
+        let path = self.path(key);
+        Box::pin(async move {
+            let path = path?;
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            // Readers never see a half written file
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".partial");
+            tokio::fs::write(&tmp, &data).await?;
+            tokio::fs::rename(&tmp, &path).await?;
+            Ok(())
+        })
+    }
+
+    fn get(&self, key: &str) -> BoxFuture<'_, Result<Option<Bytes>, StoreError>> {
+        let path = self.path(key);
+        Box::pin(async move {
+            match tokio::fs::read(path?).await {
+                Ok(data) => Ok(Some(Bytes::from(data))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn delete(&self, key: &str) -> BoxFuture<'_, Result<(), StoreError>> {
+        let path = self.path(key);
+        Box::pin(async move {
+            match tokio::fs::remove_file(path?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let root = std::env::temp_dir().join(format!("chat-local-store-{:x}", rand::random::<u64>()));
+        let store = LocalStore::new(&root);
+
+        store.put("attachments/1/abc", Bytes::from_static(b"hello"), "text/plain").await.unwrap();
+        assert_eq!(store.get("attachments/1/abc").await.unwrap().unwrap(), "hello");
+        assert!(store.get("attachments/1/missing").await.unwrap().is_none());
+        assert!(store.get("../outside").await.is_err());
+
+        store.delete("attachments/1/abc").await.unwrap();
+        store.delete("attachments/1/abc").await.unwrap();
+        assert!(store.get("attachments/1/abc").await.unwrap().is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
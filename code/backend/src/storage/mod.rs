@@ -0,0 +1,85 @@
+use std::fmt;
+use std::sync::Arc;
+
+use actix_web::web::Bytes;
+use futures::future::BoxFuture;
+
+use crate::settings::{AttachmentSettings, StorageKind};
+
+pub mod local_store;
+pub mod s3_store;
+
+#[derive(Debug)]
+pub enum StoreError {
+    InvalidKey(String),
+    Io(std::io::Error),
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::InvalidKey(key) => write!(f, "invalid object key '{}'", key),
+            StoreError::Io(e) => write!(f, "object store I/O error: {}", e),
+            StoreError::Backend(e) => write!(f, "object store error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+/// Where attachment contents live. Objects are small enough (see
+/// `attachments.max_bytes`) to be passed around whole.
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, key: &str, data: Bytes, content_type: &str) -> BoxFuture<'_, Result<(), StoreError>>;
+    /// `None` if there is no object under `key`.
+    fn get(&self, key: &str) -> BoxFuture<'_, Result<Option<Bytes>, StoreError>>;
+    /// Deleting a missing object is not an error.
+    fn delete(&self, key: &str) -> BoxFuture<'_, Result<(), StoreError>>;
+}
+
+/// Keys are generated by the server and limited to `[a-z0-9._-]` segments
+/// separated by `/`, so they map onto paths and URLs without escaping.
+pub fn validate_key(key: &str) -> Result<(), StoreError> {
+    let valid = !key.is_empty()
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(StoreError::InvalidKey(key.to_string()))
+    }
+}
+
+/// Builds the store selected by `attachments.storage` (`local` or `s3`).
+pub fn from_settings(settings: &AttachmentSettings) -> Result<Arc<dyn ObjectStore>, StoreError> {
+    match (&settings.storage, &settings.s3) {
+        (StorageKind::S3, Some(s3)) => Ok(Arc::new(s3_store::S3Store::new(s3)?)),
+        // Validated by Settings::load
+        (StorageKind::S3, None) => Err(StoreError::Backend("attachments.s3 is not configured".to_string())),
+        (StorageKind::Local, _) => Ok(Arc::new(local_store::LocalStore::new(&settings.local_dir))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_cannot_escape_the_store() {
+        assert!(validate_key("attachments/1/0a1b2c.thumb.png").is_ok());
+        for key in ["", "/etc/passwd", "a/../b", "a//b", "..", "A/b", "a b", "a\\b", "a/"] {
+            assert!(validate_key(key).is_err(), "{:?}", key);
+        }
+    }
+}